@@ -0,0 +1,402 @@
+//! Recovery of deleted table records from the free space of b-tree pages.
+//!
+//! Deleting a cell doesn't erase it, unless `secure_delete` is on. The cell becomes a freeblock,
+//! which only overwrites its first 4 bytes with the freeblock header, or, when it's at the start
+//! of the content area, the area shrinks over it and it ends up in the unallocated space between
+//! the cell pointer array and the content area. Both are scanned for bytes which decode
+//! as a plausible record, intact or with the overwritten leading bytes guessed.
+
+use std::ops::Range;
+
+use nom::combinator::{map, map_opt};
+use nom::multi::many0;
+use nom::number::complete::{be_u16, be_u8};
+use nom::sequence::Tuple;
+use nom::IResult;
+
+use crate::model::{CellOffset, PageLayout, Payload, SerialType, TableCellPayload, TextEncoding};
+use crate::parser::{column_values, leaf_table_page_lenient};
+use crate::varint::{be_i64_varint, be_u64_varint};
+
+/// Size of the freeblock header: offset of the next freeblock and the size of this one
+const FREEBLOCK_HEADER_SIZE: usize = 4;
+/// Fragments of up to 3 bytes are too small for a freeblock and can trail a freed cell
+const MAX_FRAGMENT_SIZE: usize = 3;
+/// Largest record header size which fits into the single byte overwritten by a freeblock
+const MAX_SINGLE_BYTE_VARINT: usize = 0x7f;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FreeSpace {
+    /// Freed cell in the middle of the content area, its first 4 bytes are lost
+    Freeblock,
+    /// Gap between the cell pointer array and the content area
+    Unallocated,
+    /// Cell of a former leaf page which is now on the freelist
+    FreePage,
+}
+
+/// How much of the record was read as is, from least to most certain
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Confidence {
+    /// Record header size, and possibly the first serial type, were overwritten
+    /// and have been guessed so the record fills the freeblock
+    Low,
+    /// Record is intact, but the cell header with the payload size and the rowid is lost
+    Medium,
+    /// Whole cell is intact and its payload size matches the record
+    High,
+}
+
+pub struct CarvedRecord<'a> {
+    pub page_no: u32,
+    /// Offset within the page of the cell, or of the record when the cell header is lost
+    pub offset: usize,
+    pub free_space: FreeSpace,
+    pub rowid: Option<i64>,
+    pub payload: TableCellPayload<'a>,
+    pub confidence: Confidence,
+}
+
+/// Fields of a b-tree page header which describe its free space
+struct FreeSpaceHeader {
+    is_table: bool,
+    header_size: usize,
+    first_freeblock_offset: u16,
+    no_cells: u16,
+    cell_content_offset: CellOffset,
+}
+
+fn free_space_header(i: &[u8]) -> IResult<&[u8], FreeSpaceHeader> {
+    let (i, (is_table, header_size)) = map_opt(be_u8, |page_type| match page_type {
+        0x02 => Some((false, 12)),
+        0x05 => Some((true, 12)),
+        0x0a => Some((false, 8)),
+        0x0d => Some((true, 8)),
+        _ => None,
+    })(i)?;
+    let (i, (first_freeblock_offset, no_cells, cell_content_offset)) =
+        (be_u16, be_u16, map(be_u16, CellOffset)).parse(i)?;
+
+    Ok((
+        i,
+        FreeSpaceHeader {
+            is_table,
+            header_size,
+            first_freeblock_offset,
+            no_cells,
+            cell_content_offset,
+        },
+    ))
+}
+
+/// Freeblocks and the unallocated region of a b-tree page, as offsets within the page.
+/// `header_offset` is 100 for the first page and 0 for the rest, the input is expected to be
+/// exactly the usable part of the page. The freeblock chain is followed while it's sane.
+pub fn free_space(page: &[u8], header_offset: usize) -> Vec<(FreeSpace, Range<usize>)> {
+    let header = match page.get(header_offset..).map(free_space_header) {
+        Some(Ok((_, header))) => header,
+        _ => return vec![],
+    };
+    let mut regions = vec![];
+
+    let pointers_end = header_offset + header.header_size + header.no_cells as usize * 2;
+    let content_start = (header.cell_content_offset.real_offset() as usize).min(page.len());
+    if pointers_end < content_start {
+        regions.push((FreeSpace::Unallocated, pointers_end..content_start));
+    }
+
+    // freeblocks are kept in ascending order, which also guards from loops
+    let mut offset = header.first_freeblock_offset as usize;
+    let mut min_offset = content_start.max(pointers_end);
+    while offset != 0 && offset >= min_offset && offset + FREEBLOCK_HEADER_SIZE <= page.len() {
+        let next = u16::from_be_bytes([page[offset], page[offset + 1]]) as usize;
+        let size = u16::from_be_bytes([page[offset + 2], page[offset + 3]]) as usize;
+        if size < FREEBLOCK_HEADER_SIZE || offset + size > page.len() {
+            break;
+        }
+
+        regions.push((FreeSpace::Freeblock, offset..offset + size));
+        min_offset = offset + size;
+        offset = next;
+    }
+
+    regions
+}
+
+/// Deleted records found in the free space of a table b-tree page, see `free_space`.
+/// Index pages are skipped, their entries duplicate the columns of table records.
+pub fn carve_page(
+    page_no: u32,
+    page: &[u8],
+    header_offset: usize,
+    text_encoding: TextEncoding,
+) -> Vec<CarvedRecord<'_>> {
+    match page.get(header_offset..).map(free_space_header) {
+        Some(Ok((_, header))) if header.is_table => {}
+        _ => return vec![],
+    }
+
+    let mut records = vec![];
+    for (free_space, region) in free_space(page, header_offset) {
+        // freeblock header bytes can't be a part of a cell header
+        let mut intact_start = match free_space {
+            FreeSpace::Freeblock => region.start + FREEBLOCK_HEADER_SIZE,
+            FreeSpace::Unallocated | FreeSpace::FreePage => region.start,
+        };
+
+        let mut offset = region.start;
+        while offset < region.end {
+            let i = &page[offset..region.end];
+            let intact = match offset >= intact_start {
+                true => plausible_record(i, text_encoding),
+                false => None,
+            };
+
+            let (len, record) = match intact {
+                Some((len, payload)) => {
+                    let cell = cell_header_before(&page[intact_start..offset], len);
+                    let record = CarvedRecord {
+                        page_no,
+                        offset: cell.map_or(offset, |(cell_start, _)| intact_start + cell_start),
+                        free_space,
+                        rowid: cell.map(|(_, rowid)| rowid),
+                        payload,
+                        confidence: match cell {
+                            Some(_) => Confidence::High,
+                            None => Confidence::Medium,
+                        },
+                    };
+                    (len, record)
+                }
+                None => match freed_cell(i, text_encoding) {
+                    Some((len, payload)) => {
+                        let record = CarvedRecord {
+                            page_no,
+                            offset,
+                            free_space,
+                            rowid: None,
+                            payload,
+                            confidence: Confidence::Low,
+                        };
+                        (len, record)
+                    }
+                    None => {
+                        offset += 1;
+                        continue;
+                    }
+                },
+            };
+
+            records.push(record);
+            offset += len;
+            intact_start = offset;
+        }
+    }
+
+    records
+}
+
+/// Rows of a free page which used to be a table leaf, followed by the deleted records found
+/// in its free space. Cells which spill onto overflow pages have only their local part decoded,
+/// the overflow pages were most likely freed and reused too.
+/// The input is expected to be exactly the usable part of the page.
+pub fn carve_free_page(
+    page_no: u32,
+    page: &[u8],
+    layout: PageLayout,
+    text_encoding: TextEncoding,
+) -> Vec<CarvedRecord<'_>> {
+    let leaf = match leaf_table_page_lenient(layout)(page) {
+        Ok((_, leaf)) => leaf,
+        Err(_) => return vec![],
+    };
+
+    let rows = leaf
+        .cell_pointers
+        .into_iter()
+        .zip(leaf.cells)
+        .map(|(ptr, cell)| CarvedRecord {
+            page_no,
+            offset: ptr as usize,
+            free_space: FreeSpace::FreePage,
+            rowid: Some(cell.rowid),
+            payload: cell.payload,
+            confidence: Confidence::High,
+        });
+
+    rows.chain(carve_page(page_no, page, 0, text_encoding))
+        .collect()
+}
+
+/// Cell which starts with a freeblock header, with its length.
+/// Cells freed at the start of the content area keep the header too,
+/// the area just shrinks over them.
+fn freed_cell(i: &[u8], text_encoding: TextEncoding) -> Option<(usize, TableCellPayload<'_>)> {
+    let size = u16::from_be_bytes([*i.get(2)?, *i.get(3)?]) as usize;
+    if size <= FREEBLOCK_HEADER_SIZE || size > i.len() {
+        return None;
+    }
+
+    reconstruct_record(&i[..size], text_encoding)
+}
+
+/// Record which starts at the beginning of the input, with its length.
+/// Serial types must be valid, values must fit and text must decode,
+/// records of NULLs only are rejected as they match almost any run of small bytes.
+fn plausible_record(
+    i: &[u8],
+    text_encoding: TextEncoding,
+) -> Option<(usize, TableCellPayload<'_>)> {
+    let (rest, header_size) = be_u64_varint(i).ok()?;
+    let size_len = i.len() - rest.len();
+    let types_len = (header_size as usize).checked_sub(size_len)?;
+    if types_len == 0 || types_len > rest.len() {
+        return None;
+    }
+
+    let (types, values) = rest.split_at(types_len);
+    let (left, column_types) = many0(map(be_u64_varint, SerialType::from))(types).ok()?;
+    if !left.is_empty() {
+        return None;
+    }
+
+    let (after, column_values) = plausible_values(values, &column_types, text_encoding)?;
+
+    Some((
+        i.len() - after.len(),
+        TableCellPayload {
+            header_size,
+            column_types,
+            column_values,
+        },
+    ))
+}
+
+fn plausible_values<'a>(
+    i: &'a [u8],
+    column_types: &[SerialType],
+    text_encoding: TextEncoding,
+) -> Option<(&'a [u8], Vec<Option<Payload<'a>>>)> {
+    if column_types.iter().all(|t| matches!(t, SerialType::Null)) {
+        return None;
+    }
+
+    let (after, values) = column_values(column_types)(i).ok()?;
+    let decodes = values.iter().all(|v| match v {
+        // NUL is valid, but unlikely in text and the usual content of unused space
+        Some(Payload::Text(text)) => text
+            .decode_strict(text_encoding)
+            .is_ok_and(|text| !text.contains('\0')),
+        _ => true,
+    });
+
+    decodes.then_some((after, values))
+}
+
+/// Payload size and rowid varints which end right where the record starts.
+/// Returns the cell offset within the input and the rowid.
+fn cell_header_before(i: &[u8], record_len: usize) -> Option<(usize, i64)> {
+    // each varint takes at most 9 bytes
+    (i.len().saturating_sub(18)..i.len().saturating_sub(1))
+        .rev()
+        .find_map(|start| {
+            let (rest, payload_size) = be_u64_varint(&i[start..]).ok()?;
+            let (rest, rowid) = be_i64_varint(rest).ok()?;
+            (rest.is_empty() && payload_size == record_len as u64).then_some((start, rowid))
+        })
+}
+
+/// Record of a freed cell whose leading bytes were overwritten by the freeblock header.
+///
+/// The header covers the payload size and the rowid, at least a byte each, so the record
+/// header size is at offset 3 or 2. In the latter case the first serial type is lost too and
+/// is assumed to be NULL, which is what an INTEGER PRIMARY KEY column is stored as.
+/// The header is read type by type until the values fill the rest of the freeblock,
+/// a record leaving a trailing fragment is only taken if none fills it exactly.
+fn reconstruct_record(
+    freeblock: &[u8],
+    text_encoding: TextEncoding,
+) -> Option<(usize, TableCellPayload<'_>)> {
+    (0..=MAX_FRAGMENT_SIZE).find_map(|fragment_size| {
+        [3, 2].into_iter().find_map(|header_size_offset| {
+            reconstruct_record_with(freeblock, header_size_offset, fragment_size, text_encoding)
+        })
+    })
+}
+
+fn reconstruct_record_with(
+    freeblock: &[u8],
+    header_size_offset: usize,
+    fragment_size: usize,
+    text_encoding: TextEncoding,
+) -> Option<(usize, TableCellPayload<'_>)> {
+    let mut column_types = vec![SerialType::Null; FREEBLOCK_HEADER_SIZE - header_size_offset - 1];
+    let mut types = freeblock.get(FREEBLOCK_HEADER_SIZE..)?;
+
+    while let Ok((rest, serial_type)) = be_u64_varint(types) {
+        types = rest;
+        column_types.push(SerialType::from(serial_type));
+
+        let header_size = freeblock.len() - types.len() - header_size_offset;
+        if header_size > MAX_SINGLE_BYTE_VARINT {
+            break;
+        }
+
+        match plausible_values(types, &column_types, text_encoding) {
+            Some((after, column_values)) if after.len() == fragment_size => {
+                return Some((
+                    freeblock.len() - after.len(),
+                    TableCellPayload {
+                        header_size: header_size as u64,
+                        column_types,
+                        column_values,
+                    },
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carve_intact_cell() {
+        let mut page = vec![0u8; 512];
+        // leaf table page without cells, its content area is empty
+        page[..8].copy_from_slice(&[0x0d, 0, 0, 0, 0, 0x02, 0, 0]);
+        // payload size, rowid, record header with TEXT(1) and I8, values
+        page[100..107].copy_from_slice(&[5, 7, 3, 15, 1, b'a', 5]);
+
+        assert_eq!(free_space(&page, 0), vec![(FreeSpace::Unallocated, 8..512)]);
+
+        let records = carve_page(3, &page, 0, TextEncoding::Utf8);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].offset, 100);
+        assert_eq!(records[0].rowid, Some(7));
+        assert_eq!(records[0].confidence, Confidence::High);
+        assert_eq!(
+            records[0].payload.column_values,
+            vec![Some("a".into()), Some(Payload::I8(5))]
+        );
+    }
+
+    #[test]
+    fn skip_broken_cells_of_free_page() {
+        let mut page = vec![0u8; 512];
+        // two cells, the second pointer is past the end of the page
+        page[..12].copy_from_slice(&[0x0d, 0, 0, 0, 2, 0x01, 0xf9, 0, 0x01, 0xf9, 0x12, 0x34]);
+        page[505..512].copy_from_slice(&[5, 7, 3, 15, 1, b'a', 5]);
+
+        let records = carve_free_page(9, &page, PageLayout::new(512), TextEncoding::Utf8);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].page_no, 9);
+        assert_eq!(records[0].offset, 505);
+        assert_eq!(records[0].free_space, FreeSpace::FreePage);
+        assert_eq!(records[0].rowid, Some(7));
+    }
+}
@@ -0,0 +1,79 @@
+//! Walks along the pages linked by their first word: overflow chains and freelist trunks.
+//!
+//! Loops aren't detected here, callers stop at a page they've reached before.
+
+use nom::Finish;
+
+use crate::error::SQLiteError;
+use crate::model::FreelistTrunkPage;
+use crate::parser::freelist_trunk_page;
+use crate::{to_pageno, Reader};
+
+/// Page numbers of an overflow chain in order, ending after the page which points to page 0
+/// or can't be read.
+pub(crate) struct OverflowChain<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    next_page_no: u32,
+}
+
+impl<'a, S: AsRef<[u8]>> OverflowChain<'a, S> {
+    /// `first_page_no` is the page number as stored in the cell.
+    pub(crate) fn new(reader: &'a Reader<S>, first_page_no: u32) -> Self {
+        OverflowChain {
+            reader,
+            next_page_no: first_page_no,
+        }
+    }
+}
+
+impl<'a, S: AsRef<[u8]>> Iterator for OverflowChain<'a, S> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page_no = Some(self.next_page_no).filter(|&p| p != 0)?;
+        self.next_page_no = to_pageno(page_no)
+            .and_then(|pageno| self.reader.page_bytes(pageno))
+            .map_or(0, |bytes| {
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            });
+
+        Some(page_no)
+    }
+}
+
+/// Freelist trunk pages in order, starting from the one in the database header.
+/// Every trunk comes with its page number, even if it can't be read, which ends the walk.
+pub(crate) struct FreelistTrunks<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    next_page_no: u32,
+}
+
+impl<'a, S: AsRef<[u8]>> FreelistTrunks<'a, S> {
+    pub(crate) fn new(reader: &'a Reader<S>) -> Self {
+        FreelistTrunks {
+            reader,
+            next_page_no: reader.header.first_freelist_page_no,
+        }
+    }
+}
+
+impl<'a, S: AsRef<[u8]>> Iterator for FreelistTrunks<'a, S> {
+    type Item = (u32, Result<FreelistTrunkPage, SQLiteError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page_no = Some(self.next_page_no).filter(|&p| p != 0)?;
+        let usable_size = self.reader.header.usable_size();
+        let trunk = to_pageno(page_no)
+            .and_then(|pageno| self.reader.page_bytes(pageno))
+            .and_then(|bytes| {
+                let (_, trunk) = freelist_trunk_page(&bytes[..usable_size]).finish()?;
+                Ok(trunk)
+            });
+        self.next_page_no = match &trunk {
+            Ok(trunk) => trunk.next_trunk_page_no.unwrap_or(0),
+            Err(_) => 0,
+        };
+
+        Some((page_no, trunk))
+    }
+}
@@ -0,0 +1,294 @@
+//! SQLite record comparison, as used to order index entries.
+//!
+//! Values of different storage classes are ordered NULL < INTEGER/REAL < TEXT < BLOB,
+//! numbers are compared numerically regardless of representation, text by collation
+//! and blobs with `memcmp`.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use crate::ddl::SortOrder;
+use crate::error::SQLiteError;
+use crate::model::{Payload, RawText, TextEncoding};
+
+/// Built-in collating functions
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Collation {
+    /// Compares bytes of the text in the database encoding
+    #[default]
+    Binary,
+    /// Same as binary, but ASCII letters are folded to lower case
+    NoCase,
+    /// Same as binary, but trailing spaces are ignored
+    RTrim,
+}
+
+impl TryFrom<&str> for Collation {
+    type Error = SQLiteError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case("BINARY") {
+            Ok(Collation::Binary)
+        } else if value.eq_ignore_ascii_case("NOCASE") {
+            Ok(Collation::NoCase)
+        } else if value.eq_ignore_ascii_case("RTRIM") {
+            Ok(Collation::RTrim)
+        } else {
+            Err(SQLiteError::UnknownCollationError(value.to_owned()))
+        }
+    }
+}
+
+/// How a single record column is ordered
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct KeyColumn {
+    pub collation: Collation,
+    pub order: SortOrder,
+}
+
+/// Compares two records column by column, only the columns present in both are compared.
+/// Columns without a `KeyColumn` are compared as BINARY ascending.
+pub fn compare_records(
+    a: &[Option<Payload>],
+    b: &[Option<Payload>],
+    columns: &[KeyColumn],
+    text_encoding: TextEncoding,
+) -> Ordering {
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let column = columns.get(i).copied().unwrap_or_default();
+            let ordering = compare_values(a.as_ref(), b.as_ref(), column.collation, text_encoding);
+            match column.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        })
+        .find(|&o| o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Compares two values, `None` being NULL.
+pub fn compare_values(
+    a: Option<&Payload>,
+    b: Option<&Payload>,
+    collation: Collation,
+    text_encoding: TextEncoding,
+) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => match (a, b) {
+            (Payload::Text(a), Payload::Text(b)) => compare_text(a, b, collation, text_encoding),
+            (Payload::Blob(a), Payload::Blob(b)) => a.as_ref().cmp(b.as_ref()),
+            (a, b) if storage_class(a) == NUMERIC && storage_class(b) == NUMERIC => {
+                compare_numbers(a, b)
+            }
+            (a, b) => storage_class(a).cmp(&storage_class(b)),
+        },
+    }
+}
+
+const NUMERIC: u8 = 1;
+
+fn storage_class(value: &Payload) -> u8 {
+    match value {
+        Payload::I8(_) | Payload::I16(_) | Payload::I32(_) | Payload::I64(_) | Payload::F64(_) => {
+            NUMERIC
+        }
+        Payload::Text(_) => 2,
+        Payload::Blob(_) => 3,
+    }
+}
+
+fn compare_numbers(a: &Payload, b: &Payload) -> Ordering {
+    match (a, b) {
+        (Payload::F64(a), Payload::F64(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Payload::F64(a), b) => compare_int_real(b.as_i64().unwrap_or_default(), *a).reverse(),
+        (a, Payload::F64(b)) => compare_int_real(a.as_i64().unwrap_or_default(), *b),
+        (a, b) => a.as_i64().cmp(&b.as_i64()),
+    }
+}
+
+/// Exact comparison, casting either side could lose precision
+fn compare_int_real(int: i64, real: f64) -> Ordering {
+    // 2^63, the first real above any integer
+    const I64_BOUND: f64 = 9223372036854775808.0;
+
+    // NaN is never stored, SQLite writes NULL instead
+    if real.is_nan() || real < -I64_BOUND {
+        Ordering::Greater
+    } else if real >= I64_BOUND {
+        Ordering::Less
+    } else {
+        let truncated = real.trunc();
+        match int.cmp(&(truncated as i64)) {
+            Ordering::Equal => truncated.partial_cmp(&real).unwrap_or(Ordering::Equal),
+            ordering => ordering,
+        }
+    }
+}
+
+fn compare_text(
+    a: &RawText,
+    b: &RawText,
+    collation: Collation,
+    text_encoding: TextEncoding,
+) -> Ordering {
+    let (a, b) = (
+        utf8_bytes(a, collation, text_encoding),
+        utf8_bytes(b, collation, text_encoding),
+    );
+
+    match collation {
+        Collation::Binary => a.cmp(&b),
+        Collation::NoCase => a
+            .iter()
+            .map(u8::to_ascii_lowercase)
+            .cmp(b.iter().map(u8::to_ascii_lowercase)),
+        Collation::RTrim => trim_spaces(&a).cmp(trim_spaces(&b)),
+    }
+}
+
+/// Only binary works on the database encoding, the rest are defined on UTF-8
+fn utf8_bytes<'b>(
+    text: &'b RawText,
+    collation: Collation,
+    text_encoding: TextEncoding,
+) -> Cow<'b, [u8]> {
+    match (collation, text_encoding) {
+        (Collation::Binary, _) | (_, TextEncoding::Utf8) => Cow::Borrowed(text.bytes()),
+        _ => Cow::Owned(text.decode(text_encoding).into_bytes()),
+    }
+}
+
+fn trim_spaces(text: &[u8]) -> &[u8] {
+    let len = text.iter().rposition(|&c| c != b' ').map_or(0, |p| p + 1);
+    &text[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: Option<Payload>, b: Option<Payload>, collation: Collation) -> Ordering {
+        compare_values(a.as_ref(), b.as_ref(), collation, TextEncoding::Utf8)
+    }
+
+    #[test]
+    fn order_storage_classes() {
+        let values = [
+            None,
+            Some(Payload::I8(-1)),
+            Some(Payload::F64(0.5)),
+            Some(Payload::I64(1)),
+            Some("".into()),
+            Some("a".into()),
+            Some(Payload::from(&[0u8][..])),
+        ];
+
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(cmp(a.clone(), b.clone(), Collation::Binary), i.cmp(&j));
+            }
+        }
+    }
+
+    #[test]
+    fn compare_integers_and_reals() {
+        let b = Collation::Binary;
+        assert_eq!(
+            cmp(Some(2i8.into()), Some(2.0f64.into()), b),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp(Some(2i8.into()), Some(2.5f64.into()), b),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(Some(3i32.into()), Some(2.5f64.into()), b),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(Some((-3i16).into()), Some((-2.5f64).into()), b),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(
+                Some(i64::MAX.into()),
+                Some(9223372036854775807.0f64.into()),
+                b
+            ),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(Some(i64::MIN.into()), Some((-1e19f64).into()), b),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(Some(1e19f64.into()), Some(i64::MAX.into()), b),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn compare_with_collations() {
+        assert_eq!(
+            cmp(Some("B".into()), Some("a".into()), Collation::Binary),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(Some("B".into()), Some("a".into()), Collation::NoCase),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(Some("ABC".into()), Some("abc".into()), Collation::NoCase),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp(Some("a  ".into()), Some("a".into()), Collation::RTrim),
+            Ordering::Equal
+        );
+        assert_eq!(
+            cmp(Some("a  ".into()), Some("a".into()), Collation::Binary),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(Some(" a".into()), Some("a".into()), Collation::RTrim),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn compare_record_prefixes() {
+        let columns = [
+            KeyColumn::default(),
+            KeyColumn {
+                collation: Collation::Binary,
+                order: SortOrder::Desc,
+            },
+        ];
+        let record: Vec<Option<Payload>> =
+            vec![Some(1i8.into()), Some(2i8.into()), Some(3i8.into())];
+
+        let compare =
+            |key: &[Option<Payload>]| compare_records(&record, key, &columns, TextEncoding::Utf8);
+
+        assert_eq!(compare(&[Some(1i8.into())]), Ordering::Equal);
+        assert_eq!(
+            compare(&[Some(1i8.into()), Some(1i8.into())]),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare(&[Some(1i8.into()), Some(3i8.into())]),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(&[Some(0i8.into()), Some(3i8.into())]),
+            Ordering::Greater
+        );
+        assert_eq!(compare(&[]), Ordering::Equal);
+    }
+}
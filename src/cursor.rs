@@ -0,0 +1,394 @@
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+
+use crate::compare::{compare_records, KeyColumn};
+use crate::error::SQLiteError;
+use crate::model::{
+    IndexCellPayload, InteriorIndexCell, LeafIndexCell, LeafTableCell, Page, Payload,
+    TableCellPayload,
+};
+use crate::{to_pageno, Reader};
+
+/// Same limit SQLite uses, real b-trees are never that deep
+pub(crate) const MAX_DEPTH: usize = 20;
+
+enum TableFrame<'a> {
+    /// Children are stored in the walk order, reversed for reverse cursors
+    Interior { children: Vec<u32>, next: usize },
+    /// Cells are stored in the walk order, reversed for reverse cursors
+    Leaf {
+        cells: vec::IntoIter<LeafTableCell<'a>>,
+    },
+}
+
+/// Walks the table b-tree in rowid order, keeping only the path from the root in memory.
+/// Yields rowid and the record with overflow pages resolved.
+///
+/// When the cursor is limited to a rowid range, the first descent goes straight to the
+/// boundary of the range and the walk stops as soon as it leaves the range.
+pub struct TableCursor<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    stack: Vec<TableFrame<'a>>,
+    start: Bound<i64>,
+    end: Bound<i64>,
+    reverse: bool,
+    /// Still descending to the first row
+    seeking: bool,
+}
+
+impl<'a, S: AsRef<[u8]>> TableCursor<'a, S> {
+    pub(crate) fn new<R: RangeBounds<i64>>(
+        reader: &'a Reader<S>,
+        root_page_no: u32,
+        range: R,
+        reverse: bool,
+    ) -> Self {
+        TableCursor {
+            reader,
+            stack: vec![TableFrame::Interior {
+                children: vec![root_page_no],
+                next: 0,
+            }],
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            reverse,
+            seeking: true,
+        }
+    }
+
+    fn before_start(&self, rowid: i64) -> bool {
+        match self.start {
+            Bound::Included(start) => rowid < start,
+            Bound::Excluded(start) => rowid <= start,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn after_end(&self, rowid: i64) -> bool {
+        match self.end {
+            Bound::Included(end) => rowid > end,
+            Bound::Excluded(end) => rowid >= end,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn push_page(&mut self, page_no: u32) -> Result<(), SQLiteError> {
+        if self.stack.len() > MAX_DEPTH {
+            return Err(SQLiteError::BTreeTooDeepError(page_no));
+        }
+
+        let frame = match self.reader.get_local_page(to_pageno(page_no)?)? {
+            Page::InteriorTable(p) => {
+                let mut children: Vec<u32> = p
+                    .cells
+                    .iter()
+                    .map(|c| c.left_child_page_no)
+                    .chain(Some(p.header.rightmost_pointer))
+                    .collect();
+
+                // left child of a cell holds rowids up to and including its key
+                let first = match (self.seeking, self.reverse) {
+                    (false, _) => 0,
+                    (true, false) => p
+                        .cells
+                        .partition_point(|c| self.before_start(c.integer_key)),
+                    (true, true) => {
+                        let last = p.cells.partition_point(|c| !self.after_end(c.integer_key));
+                        children.len() - 1 - last
+                    }
+                };
+                if self.reverse {
+                    children.reverse();
+                }
+
+                TableFrame::Interior {
+                    children,
+                    next: first,
+                }
+            }
+            Page::LeafTable(mut p) => {
+                if self.reverse {
+                    p.cells.reverse();
+                }
+                if self.seeking {
+                    self.seeking = false;
+                    p.cells.retain(|c| {
+                        if self.reverse {
+                            !self.after_end(c.rowid)
+                        } else {
+                            !self.before_start(c.rowid)
+                        }
+                    });
+                }
+
+                TableFrame::Leaf {
+                    cells: p.cells.into_iter(),
+                }
+            }
+            _ => return Err(SQLiteError::UnexpectedPageTypeError(page_no)),
+        };
+        self.stack.push(frame);
+
+        Ok(())
+    }
+
+    fn next_row(&mut self) -> Result<Option<(i64, TableCellPayload<'a>)>, SQLiteError> {
+        loop {
+            let child_page_no = match self.stack.last_mut() {
+                None => return Ok(None),
+                Some(TableFrame::Leaf { cells }) => match cells.next() {
+                    Some(mut cell) => {
+                        let out_of_range = if self.reverse {
+                            self.before_start(cell.rowid)
+                        } else {
+                            self.after_end(cell.rowid)
+                        };
+                        if out_of_range {
+                            self.stack.clear();
+                            return Ok(None);
+                        }
+
+                        self.reader.resolve_leaf_table_cell(&mut cell)?;
+                        return Ok(Some((cell.rowid, cell.payload)));
+                    }
+                    None => None,
+                },
+                Some(TableFrame::Interior { children, next }) => {
+                    let child = children.get(*next).copied();
+                    *next += 1;
+                    child
+                }
+            };
+
+            match child_page_no {
+                Some(page_no) => self.push_page(page_no)?,
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S: AsRef<[u8]>> Iterator for TableCursor<'a, S> {
+    type Item = Result<(i64, TableCellPayload<'a>), SQLiteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.next_row();
+        if row.is_err() {
+            // the tree is broken, nothing sensible could be read further
+            self.stack.clear();
+        }
+
+        row.transpose()
+    }
+}
+
+enum IndexFrame<'a> {
+    Interior {
+        cells: vec::IntoIter<InteriorIndexCell<'a>>,
+        /// Cell whose left child is being walked, yielded once the child is done
+        pending: Option<InteriorIndexCell<'a>>,
+        rightmost_pointer: Option<u32>,
+    },
+    Leaf {
+        cells: vec::IntoIter<LeafIndexCell<'a>>,
+    },
+}
+
+/// Key prefix an index cursor is limited to
+struct IndexSeek {
+    key: Vec<Option<Payload<'static>>>,
+    columns: Vec<KeyColumn>,
+    /// Still descending to the first matching entry
+    seeking: bool,
+}
+
+/// Walks the index b-tree in the index sort order, keeping only the path from the root in memory.
+/// Keys of interior pages are yielded in between their children, as they are entries too.
+///
+/// When the cursor is limited to a key prefix, the first descent skips everything ordered
+/// before the prefix and the walk stops at the first entry which doesn't match it.
+pub struct IndexCursor<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    stack: Vec<IndexFrame<'a>>,
+    seek: Option<IndexSeek>,
+}
+
+impl<'a, S: AsRef<[u8]>> IndexCursor<'a, S> {
+    pub(crate) fn new(reader: &'a Reader<S>, root_page_no: u32) -> Self {
+        IndexCursor {
+            reader,
+            stack: vec![IndexFrame::Interior {
+                cells: Vec::new().into_iter(),
+                pending: None,
+                rightmost_pointer: Some(root_page_no),
+            }],
+            seek: None,
+        }
+    }
+
+    pub(crate) fn seek(
+        reader: &'a Reader<S>,
+        root_page_no: u32,
+        key: Vec<Option<Payload<'static>>>,
+        columns: Vec<KeyColumn>,
+    ) -> Self {
+        IndexCursor {
+            seek: Some(IndexSeek {
+                key,
+                columns,
+                seeking: true,
+            }),
+            ..Self::new(reader, root_page_no)
+        }
+    }
+
+    /// Orders the entry relative to the sought prefix, everything matches without one
+    fn compare(&self, entry: &IndexCellPayload) -> Ordering {
+        self.seek.as_ref().map_or(Ordering::Equal, |seek| {
+            compare_records(
+                &entry.column_values,
+                &seek.key,
+                &seek.columns,
+                self.reader.header.db_text_encoding,
+            )
+        })
+    }
+
+    fn seeking(&self) -> bool {
+        self.seek.as_ref().is_some_and(|seek| seek.seeking)
+    }
+
+    /// Cells are compared in full, so overflow has to be read before the cell is yielded
+    fn resolve_interior(&self, cell: &mut InteriorIndexCell<'a>) -> Result<(), SQLiteError> {
+        self.reader.resolve_interior_index_cell(cell)?;
+        cell.overflow_page_no = None;
+        Ok(())
+    }
+
+    fn resolve_leaf(&self, cell: &mut LeafIndexCell<'a>) -> Result<(), SQLiteError> {
+        self.reader.resolve_leaf_index_cell(cell)?;
+        cell.overflow_page_no = None;
+        Ok(())
+    }
+
+    fn push_page(&mut self, page_no: u32) -> Result<(), SQLiteError> {
+        if self.stack.len() > MAX_DEPTH {
+            return Err(SQLiteError::BTreeTooDeepError(page_no));
+        }
+
+        let frame = match self.reader.get_local_page(to_pageno(page_no)?)? {
+            Page::InteriorIndex(mut p) => {
+                if self.seeking() {
+                    // left child of the first cell not below the prefix may still hold matches
+                    let mut first = p.cells.len();
+                    for (i, cell) in p.cells.iter_mut().enumerate() {
+                        self.resolve_interior(cell)?;
+                        if self.compare(&cell.payload) != Ordering::Less {
+                            first = i;
+                            break;
+                        }
+                    }
+                    p.cells.drain(..first);
+                }
+
+                IndexFrame::Interior {
+                    cells: p.cells.into_iter(),
+                    pending: None,
+                    rightmost_pointer: Some(p.header.rightmost_pointer),
+                }
+            }
+            Page::LeafIndex(mut p) => {
+                if self.seeking() {
+                    let mut first = p.cells.len();
+                    for (i, cell) in p.cells.iter_mut().enumerate() {
+                        self.resolve_leaf(cell)?;
+                        if self.compare(&cell.payload) != Ordering::Less {
+                            first = i;
+                            break;
+                        }
+                    }
+                    p.cells.drain(..first);
+                    if let Some(seek) = self.seek.as_mut() {
+                        seek.seeking = false;
+                    }
+                }
+
+                IndexFrame::Leaf {
+                    cells: p.cells.into_iter(),
+                }
+            }
+            _ => return Err(SQLiteError::UnexpectedPageTypeError(page_no)),
+        };
+        self.stack.push(frame);
+
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<IndexCellPayload<'a>>, SQLiteError> {
+        loop {
+            let child_page_no = match self.stack.last_mut() {
+                None => return Ok(None),
+                Some(IndexFrame::Leaf { cells }) => match cells.next() {
+                    Some(mut cell) => {
+                        self.resolve_leaf(&mut cell)?;
+                        return Ok(self.matching(cell.payload));
+                    }
+                    None => None,
+                },
+                Some(IndexFrame::Interior {
+                    cells,
+                    pending,
+                    rightmost_pointer,
+                }) => {
+                    if let Some(mut cell) = pending.take() {
+                        self.resolve_interior(&mut cell)?;
+                        return Ok(self.matching(cell.payload));
+                    }
+
+                    match cells.next() {
+                        Some(cell) => {
+                            let child = cell.left_child_page_no;
+                            *pending = Some(cell);
+                            Some(child)
+                        }
+                        None => rightmost_pointer.take(),
+                    }
+                }
+            };
+
+            match child_page_no {
+                Some(page_no) => self.push_page(page_no)?,
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    /// Ends the walk on the first entry past the sought prefix
+    fn matching(&mut self, entry: IndexCellPayload<'a>) -> Option<IndexCellPayload<'a>> {
+        if self.compare(&entry) == Ordering::Equal {
+            Some(entry)
+        } else {
+            self.stack.clear();
+            None
+        }
+    }
+}
+
+impl<'a, S: AsRef<[u8]>> Iterator for IndexCursor<'a, S> {
+    type Item = Result<IndexCellPayload<'a>, SQLiteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.next_entry();
+        if entry.is_err() {
+            // the tree is broken, nothing sensible could be read further
+            self.stack.clear();
+        }
+
+        entry.transpose()
+    }
+}
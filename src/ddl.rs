@@ -0,0 +1,1350 @@
+//! Minimal parser of the DDL statements stored in sqlite_schema.
+//!
+//! Only what's needed to interpret records is extracted: columns with their declared types and
+//! constraints of CREATE TABLE, and indexed columns of CREATE INDEX.
+//! Expressions (CHECK, DEFAULT (...), partial index WHERE) are kept as source text.
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_until, take_while, take_while1};
+use nom::character::complete::{anychar, char, digit0, digit1, hex_digit0, multispace1, one_of};
+use nom::combinator::{map, opt, recognize, value};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, tuple};
+use nom::IResult;
+
+use crate::error::SQLiteError;
+use crate::model::Payload;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// How the generated column value is kept, virtual ones are not stored in the record
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Generated {
+    Virtual,
+    Stored,
+}
+
+/// Value of the DEFAULT clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefaultValue {
+    Null,
+    /// `text` is the literal the column affinity is applied to, the same way SQLite does:
+    /// as written with its sign, or the value itself if it fits in 32 bits.
+    /// `None` for TRUE/FALSE and converted values, which are never converted to text.
+    Integer {
+        value: i64,
+        text: Option<String>,
+    },
+    /// `text` is the literal as written with its sign, `None` for converted values
+    Real {
+        value: f64,
+        text: Option<String>,
+    },
+    Text(String),
+    Blob(Vec<u8>),
+    /// CURRENT_TIME/CURRENT_DATE/CURRENT_TIMESTAMP or parenthesized expression, as written
+    Expression(String),
+}
+
+impl DefaultValue {
+    /// Value as it would appear in a record, `None` for NULL and expressions.
+    pub fn to_payload(&self) -> Option<Payload<'static>> {
+        match self {
+            DefaultValue::Null | DefaultValue::Expression(_) => None,
+            DefaultValue::Integer { value, .. } => Some(Payload::I64(*value)),
+            DefaultValue::Real { value, .. } => Some(Payload::F64(*value)),
+            DefaultValue::Text(v) => Some(Payload::from(v.as_str()).into_owned()),
+            DefaultValue::Blob(v) => Some(Payload::from(v.as_slice()).into_owned()),
+        }
+    }
+
+    /// Value converted the way SQLite converts values stored in a column with the affinity.
+    pub fn with_affinity(&self, affinity: Affinity) -> DefaultValue {
+        match (affinity, self) {
+            // numeric literals are converted from their text, which columns without a type
+            // take as a number
+            (
+                _,
+                DefaultValue::Integer {
+                    text: Some(text), ..
+                }
+                | DefaultValue::Real {
+                    text: Some(text), ..
+                },
+            ) => {
+                let affinity = match affinity {
+                    Affinity::Blob => Affinity::Numeric,
+                    _ => affinity,
+                };
+                DefaultValue::Text(text.clone()).with_affinity(affinity)
+            }
+            (Affinity::Real, &DefaultValue::Integer { value, .. }) => DefaultValue::Real {
+                value: value as f64,
+                text: None,
+            },
+            (Affinity::Real, DefaultValue::Text(v)) => match numeric_text(v) {
+                Some(DefaultValue::Integer { value, .. }) => DefaultValue::Real {
+                    value: value as f64,
+                    text: None,
+                },
+                Some(n) => n,
+                None => self.clone(),
+            },
+            (Affinity::Numeric | Affinity::Integer, DefaultValue::Text(v)) => {
+                numeric_text(v).map_or_else(|| self.clone(), |n| n.with_affinity(affinity))
+            }
+            // reals without a fractional part are stored as integers
+            (Affinity::Numeric | Affinity::Integer, &DefaultValue::Real { value, .. })
+                if value.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&value) =>
+            {
+                DefaultValue::Integer {
+                    value: value as i64,
+                    text: None,
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// How values are converted before they're stored in a column, derived from its type name
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// Affinity of the declared type, the first of SQLite rules that applies wins.
+    pub fn of_type(declared_type: Option<&str>) -> Affinity {
+        let declared_type = match declared_type {
+            Some(t) => t.to_ascii_uppercase(),
+            None => return Affinity::Blob,
+        };
+        let has = |s: &str| declared_type.contains(s);
+
+        if has("INT") {
+            Affinity::Integer
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            Affinity::Text
+        } else if has("BLOB") {
+            Affinity::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+/// Text which is a well-formed number, ignoring surrounding spaces.
+fn numeric_text(text: &str) -> Option<DefaultValue> {
+    let text = text.trim();
+    let well_formed = text.bytes().any(|b| b.is_ascii_digit())
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+    if !well_formed {
+        return None;
+    }
+
+    match text.parse::<i64>() {
+        Ok(value) => Some(DefaultValue::Integer { value, text: None }),
+        Err(_) => text
+            .parse::<f64>()
+            .ok()
+            .map(|value| DefaultValue::Real { value, text: None }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColumnDefinition {
+    pub name: String,
+    /// Type name as written, e.g. `VARCHAR(255)`, `None` when omitted
+    pub declared_type: Option<String>,
+    pub primary_key: bool,
+    pub autoincrement: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    pub default: Option<DefaultValue>,
+    pub collation: Option<String>,
+    pub generated: Option<Generated>,
+}
+
+impl ColumnDefinition {
+    pub fn affinity(&self) -> Affinity {
+        Affinity::of_type(self.declared_type.as_deref())
+    }
+}
+
+/// Column of an index or a primary key
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexedColumn {
+    /// `None` when the index is on an expression
+    pub name: Option<String>,
+    /// Source of the expression, for indexes on expressions only
+    pub expression: Option<String>,
+    pub collation: Option<String>,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TableDefinition {
+    pub name: String,
+    pub columns: Vec<ColumnDefinition>,
+    /// Primary key columns in the key order, declared either on a column or on the table
+    pub primary_key: Vec<IndexedColumn>,
+    /// Columns of every PRIMARY KEY and UNIQUE constraint, in the declaration order
+    pub key_constraints: Vec<Vec<IndexedColumn>>,
+    /// Column which is an alias for the rowid (INTEGER PRIMARY KEY)
+    pub rowid_alias: Option<usize>,
+    pub without_rowid: bool,
+    pub strict: bool,
+}
+
+impl TableDefinition {
+    /// Position of the column by name, case insensitive.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|c| c.name.as_str())
+    }
+
+    /// Columns which have a value in the record, virtual generated columns are computed on read.
+    pub fn stored_columns(&self) -> impl Iterator<Item = &ColumnDefinition> {
+        self.columns
+            .iter()
+            .filter(|c| c.generated != Some(Generated::Virtual))
+    }
+
+    /// Positions of the declared columns in the order their values are stored in the record.
+    /// WITHOUT ROWID tables store the primary key columns first, then the rest in declared order.
+    pub fn record_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = Vec::with_capacity(self.columns.len());
+        if self.without_rowid {
+            for key in self.primary_key.iter() {
+                let i = key.name.as_deref().and_then(|n| self.column_index(n));
+                if let Some(i) = i.filter(|i| !order.contains(i)) {
+                    order.push(i);
+                }
+            }
+        }
+
+        for (i, column) in self.columns.iter().enumerate() {
+            if column.generated != Some(Generated::Virtual) && !order.contains(&i) {
+                order.push(i);
+            }
+        }
+
+        order
+    }
+
+    /// Places record values at the positions of their declared columns,
+    /// columns without a stored value are NULL.
+    pub fn declared_order<'a>(&self, values: Vec<Option<Payload<'a>>>) -> Vec<Option<Payload<'a>>> {
+        let mut row: Vec<Option<Payload<'a>>> = self.columns.iter().map(|_| None).collect();
+        for (i, value) in self.record_order().into_iter().zip(values) {
+            row[i] = value;
+        }
+
+        row
+    }
+
+    /// Values in declared column order as `SELECT *` returns them: the rowid takes the place
+    /// of its alias column, which is stored as NULL, and columns added by ALTER TABLE after
+    /// the row was written get their DEFAULT, converted to the column affinity.
+    /// Virtual generated columns are not computed.
+    ///
+    /// Fails if such a DEFAULT is an expression, which ALTER TABLE doesn't allow.
+    pub fn row_values<'a>(
+        &self,
+        rowid: Option<i64>,
+        values: Vec<Option<Payload<'a>>>,
+    ) -> Result<Vec<Option<Payload<'a>>>, SQLiteError> {
+        let stored = values.len();
+        let mut row = self.declared_order(values);
+
+        for i in self.record_order().into_iter().skip(stored) {
+            let column = &self.columns[i];
+            row[i] = match &column.default {
+                Some(DefaultValue::Expression(e)) => {
+                    return Err(SQLiteError::DefaultValueError(e.clone()))
+                }
+                Some(default) => default.with_affinity(column.affinity()).to_payload(),
+                None => None,
+            };
+        }
+        if let (Some(i), Some(rowid)) = (self.rowid_alias, rowid) {
+            row[i] = Some(Payload::I64(rowid));
+        }
+
+        Ok(row)
+    }
+
+    /// Collation of the indexed column as written, or of the table column it refers to.
+    /// `None` when it's BINARY by default.
+    pub fn collation_of<'a>(&'a self, column: &'a IndexedColumn) -> Option<&'a str> {
+        column.collation.as_deref().or_else(|| {
+            let i = self.column_index(column.name.as_deref()?)?;
+            self.columns[i].collation.as_deref()
+        })
+    }
+
+    /// Both refer to the same table column with the same collation,
+    /// which is how SQLite tells that a key column is a duplicate.
+    pub fn same_key_column(&self, a: &IndexedColumn, b: &IndexedColumn) -> bool {
+        let collation = |c| self.collation_of(c).unwrap_or("BINARY");
+        match (a.name.as_deref(), b.name.as_deref()) {
+            (Some(a_name), Some(b_name)) => {
+                a_name.eq_ignore_ascii_case(b_name)
+                    && collation(a).eq_ignore_ascii_case(collation(b))
+            }
+            _ => false,
+        }
+    }
+
+    /// Key of the automatic index `sqlite_autoindex_<table>_<n>`. SQLite numbers these in
+    /// the order of PRIMARY KEY and UNIQUE constraints, skipping the rowid alias and
+    /// constraints on the same columns with the same collations as an earlier one.
+    pub fn automatic_index(&self, n: usize) -> Option<&[IndexedColumn]> {
+        let same_key = |a: &[IndexedColumn], b: &[IndexedColumn]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.same_key_column(a, b))
+        };
+
+        let mut keys: Vec<&[IndexedColumn]> = Vec::new();
+        for key in self.key_constraints.iter() {
+            let is_rowid_alias = self.rowid_alias.is_some() && *key == self.primary_key;
+            if !is_rowid_alias && !keys.iter().any(|k| same_key(k, key)) {
+                keys.push(key);
+            }
+        }
+
+        keys.get(n.checked_sub(1)?).copied()
+    }
+
+    /// Pairs record values of a rowid table with the names of the stored columns.
+    pub fn named<'a>(&self, values: Vec<Option<Payload<'a>>>) -> Vec<(&str, Option<Payload<'a>>)> {
+        self.stored_columns()
+            .map(|c| c.name.as_str())
+            .zip(values)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// Condition of a partial index, as written
+    pub where_clause: Option<String>,
+}
+
+/// Parses CREATE TABLE statement as stored in sqlite_schema.
+pub fn parse_create_table(sql: &str) -> Result<TableDefinition, SQLiteError> {
+    let mut p = Tokens::new(sql)?;
+
+    p.expect_keyword("CREATE")?;
+    let _ = p.eat_keyword("TEMP") || p.eat_keyword("TEMPORARY");
+    p.expect_keyword("TABLE")?;
+    p.if_not_exists()?;
+    let name = p.qualified_name()?;
+
+    let mut table = TableDefinition {
+        name,
+        ..TableDefinition::default()
+    };
+
+    // "INTEGER PRIMARY KEY DESC" is not an alias, for compatibility with an old bug
+    let mut descending_column_key = false;
+
+    p.expect_punct('(')?;
+    loop {
+        if p.is_table_constraint() {
+            p.table_constraint(&mut table)?;
+        } else {
+            let (column, key_order) = p.column_definition()?;
+            if let Some(order) = key_order {
+                descending_column_key = order == SortOrder::Desc;
+                table.primary_key = vec![IndexedColumn {
+                    name: Some(column.name.clone()),
+                    order,
+                    ..IndexedColumn::default()
+                }];
+                table.key_constraints.push(table.primary_key.clone());
+            }
+            if column.unique {
+                table.key_constraints.push(vec![IndexedColumn {
+                    name: Some(column.name.clone()),
+                    ..IndexedColumn::default()
+                }]);
+            }
+            table.columns.push(column);
+        }
+
+        if !p.eat_punct(',') {
+            break;
+        }
+    }
+    p.expect_punct(')')?;
+
+    loop {
+        if p.eat_keyword("WITHOUT") {
+            p.expect_keyword("ROWID")?;
+            table.without_rowid = true;
+        } else if p.eat_keyword("STRICT") {
+            table.strict = true;
+        }
+
+        if !p.eat_punct(',') {
+            break;
+        }
+    }
+    p.end()?;
+
+    for key in table.primary_key.iter() {
+        if let Some(i) = key.name.as_deref().and_then(|n| table.column_index(n)) {
+            table.columns[i].primary_key = true;
+        }
+    }
+
+    table.rowid_alias = match table.primary_key.as_slice() {
+        [key] if !table.without_rowid && !descending_column_key => key
+            .name
+            .as_deref()
+            .and_then(|name| table.column_index(name))
+            .filter(|&i| {
+                table.columns[i]
+                    .declared_type
+                    .as_deref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case("INTEGER"))
+            }),
+        _ => None,
+    };
+
+    Ok(table)
+}
+
+/// Parses CREATE INDEX statement as stored in sqlite_schema.
+pub fn parse_create_index(sql: &str) -> Result<IndexDefinition, SQLiteError> {
+    let mut p = Tokens::new(sql)?;
+
+    p.expect_keyword("CREATE")?;
+    let unique = p.eat_keyword("UNIQUE");
+    p.expect_keyword("INDEX")?;
+    p.if_not_exists()?;
+    let name = p.qualified_name()?;
+    p.expect_keyword("ON")?;
+    let table = p.name()?;
+    let columns = p.indexed_columns()?;
+    let where_clause = if p.eat_keyword("WHERE") {
+        let start = p.offset();
+        while p.peek().is_some() && !p.is_punct(';') {
+            p.pos += 1;
+        }
+        Some(p.source(start, p.offset()))
+    } else {
+        None
+    };
+    p.end()?;
+
+    Ok(IndexDefinition {
+        name,
+        table,
+        unique,
+        columns,
+        where_clause,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// Bare identifier or keyword
+    Word(String),
+    /// Quoted identifier
+    Name(String),
+    Str(String),
+    Number(String),
+    Blob(Vec<u8>),
+    Punct(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn comment(i: &str) -> IResult<&str, &str> {
+    alt((
+        recognize(pair(tag("--"), take_while(|c| c != '\n'))),
+        recognize(tuple((tag("/*"), take_until("*/"), tag("*/")))),
+    ))(i)
+}
+
+fn separator(i: &str) -> IResult<&str, ()> {
+    value((), many0(alt((multispace1, comment))))(i)
+}
+
+/// Quoted with `quote`, which is escaped by doubling
+fn quoted(quote: char) -> impl FnMut(&str) -> IResult<&str, String> {
+    move |i| {
+        let (mut rest, _) = char(quote)(i)?;
+        let mut res = String::new();
+        loop {
+            let (r, chunk) = take_while(|c| c != quote)(rest)?;
+            res.push_str(chunk);
+            let (r, _) = char(quote)(r)?;
+            match char::<&str, nom::error::Error<&str>>(quote)(r) {
+                Ok((r, _)) => {
+                    res.push(quote);
+                    rest = r;
+                }
+                Err(_) => return Ok((r, res)),
+            }
+        }
+    }
+}
+
+fn number(i: &str) -> IResult<&str, &str> {
+    alt((
+        recognize(pair(tag_no_case("0x"), hex_digit0)),
+        recognize(tuple((
+            alt((
+                recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+                recognize(pair(char('.'), digit1)),
+            )),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        ))),
+    ))(i)
+}
+
+fn blob(i: &str) -> IResult<&str, Vec<u8>> {
+    let (rest, hex) = preceded(one_of("xX"), quoted('\''))(i)?;
+    let bytes = (0..hex.len() / 2)
+        .map(|b| u8::from_str_radix(hex.get(b * 2..b * 2 + 2).unwrap_or(""), 16))
+        .collect::<Result<Vec<u8>, _>>();
+
+    match bytes {
+        Ok(bytes) if hex.len() % 2 == 0 => Ok((rest, bytes)),
+        _ => Err(nom::Err::Error(nom::error::Error::new(
+            i,
+            nom::error::ErrorKind::HexDigit,
+        ))),
+    }
+}
+
+fn token_kind(i: &str) -> IResult<&str, TokenKind> {
+    alt((
+        map(blob, TokenKind::Blob),
+        map(take_while1(is_word_char), |w: &str| {
+            if w.starts_with(|c: char| c.is_ascii_digit()) {
+                TokenKind::Number(w.to_owned())
+            } else {
+                TokenKind::Word(w.to_owned())
+            }
+        }),
+        map(number, |n: &str| TokenKind::Number(n.to_owned())),
+        map(quoted('\''), TokenKind::Str),
+        map(quoted('"'), TokenKind::Name),
+        map(quoted('`'), TokenKind::Name),
+        map(
+            delimited(char('['), take_while(|c| c != ']'), char(']')),
+            |n: &str| TokenKind::Name(n.to_owned()),
+        ),
+        map(anychar, TokenKind::Punct),
+    ))(i)
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, SQLiteError> {
+    let error = |i: &str| SQLiteError::SqlParsingError(format!("can't tokenize at `{}`", i));
+
+    let mut tokens = Vec::new();
+    let (mut i, _) = separator(sql).map_err(|_| error(sql))?;
+    while !i.is_empty() {
+        let start = sql.len() - i.len();
+        // numbers starting with a digit might be swallowed as words, e.g. `1e5` or `1.5`
+        let (rest, kind) = match number(i) {
+            Ok((rest, n)) if !rest.starts_with(is_word_char) => {
+                (rest, TokenKind::Number(n.to_owned()))
+            }
+            _ => token_kind(i).map_err(|_| error(i))?,
+        };
+        let end = sql.len() - rest.len();
+        tokens.push(Token { kind, start, end });
+
+        let (rest, _) = separator(rest).map_err(|_| error(rest))?;
+        i = rest;
+    }
+
+    Ok(tokens)
+}
+
+/// Keywords which end the type name of a column
+const COLUMN_CONSTRAINTS: &[&str] = &[
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+struct Tokens<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(sql: &'a str) -> Result<Self, SQLiteError> {
+        Ok(Tokens {
+            sql,
+            tokens: tokenize(sql)?,
+            pos: 0,
+        })
+    }
+
+    fn error(&self, expected: &str) -> SQLiteError {
+        let found = match self.peek() {
+            Some(token) => &self.sql[token.start..],
+            None => "end of statement",
+        };
+        SQLiteError::SqlParsingError(format!("expected {}, found `{}`", expected, found))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn offset(&self) -> usize {
+        self.peek().map_or(self.sql.len(), |t| t.start)
+    }
+
+    /// Source text between the offsets, till the end of the last token before `end`
+    fn source(&self, start: usize, end: usize) -> String {
+        let end = self.tokens[..self.pos]
+            .iter()
+            .rev()
+            .map(|t| t.end)
+            .find(|&e| e <= end)
+            .unwrap_or(end);
+        self.sql[start..end.max(start)].to_owned()
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Word(w), .. }) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_keyword_at(&self, offset: usize, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos + offset), Some(Token { kind: TokenKind::Word(w), .. }) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let is = self.is_keyword(keyword);
+        if is {
+            self.pos += 1;
+        }
+        is
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SQLiteError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(keyword))
+        }
+    }
+
+    fn is_punct(&self, punct: char) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Punct(p), .. }) if *p == punct)
+    }
+
+    fn eat_punct(&mut self, punct: char) -> bool {
+        let is = self.is_punct(punct);
+        if is {
+            self.pos += 1;
+        }
+        is
+    }
+
+    fn expect_punct(&mut self, punct: char) -> Result<(), SQLiteError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", punct)))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), SQLiteError> {
+        self.eat_punct(';');
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error("end of statement")),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, SQLiteError> {
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Word(n)) | Some(TokenKind::Name(n)) | Some(TokenKind::Str(n)) => {
+                let name = n.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("name")),
+        }
+    }
+
+    /// Name optionally prefixed with the schema name, which is dropped
+    fn qualified_name(&mut self) -> Result<String, SQLiteError> {
+        let name = self.name()?;
+        if self.eat_punct('.') {
+            self.name()
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn if_not_exists(&mut self) -> Result<(), SQLiteError> {
+        if self.eat_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+        Ok(())
+    }
+
+    /// Skips the parenthesized group, returning its contents as written
+    fn parenthesized(&mut self) -> Result<String, SQLiteError> {
+        self.expect_punct('(')?;
+        let start = self.offset();
+        let mut depth = 1;
+        loop {
+            match self.peek().map(|t| &t.kind) {
+                None => return Err(self.error("`)`")),
+                Some(TokenKind::Punct('(')) => depth += 1,
+                Some(TokenKind::Punct(')')) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let inner = self.source(start, self.offset());
+                        self.pos += 1;
+                        return Ok(inner);
+                    }
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn conflict_clause(&mut self) -> Result<(), SQLiteError> {
+        if self.eat_keyword("ON") {
+            self.expect_keyword("CONFLICT")?;
+            self.name()?;
+        }
+        Ok(())
+    }
+
+    fn sort_order(&mut self) -> SortOrder {
+        if self.eat_keyword("DESC") {
+            SortOrder::Desc
+        } else {
+            self.eat_keyword("ASC");
+            SortOrder::Asc
+        }
+    }
+
+    fn indexed_columns(&mut self) -> Result<Vec<IndexedColumn>, SQLiteError> {
+        self.expect_punct('(')?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.indexed_column()?);
+            if !self.eat_punct(',') {
+                break;
+            }
+        }
+        self.expect_punct(')')?;
+
+        Ok(columns)
+    }
+
+    fn indexed_column(&mut self) -> Result<IndexedColumn, SQLiteError> {
+        let is_column_end = |p: &Self, offset: usize| {
+            p.is_keyword_at(offset, "COLLATE")
+                || p.is_keyword_at(offset, "ASC")
+                || p.is_keyword_at(offset, "DESC")
+                || matches!(
+                    p.tokens.get(p.pos + offset).map(|t| &t.kind),
+                    Some(TokenKind::Punct(',')) | Some(TokenKind::Punct(')'))
+                )
+        };
+
+        let mut column = IndexedColumn::default();
+        let is_name = matches!(
+            self.peek().map(|t| &t.kind),
+            Some(TokenKind::Word(_)) | Some(TokenKind::Name(_))
+        );
+        if is_name && is_column_end(self, 1) {
+            column.name = Some(self.name()?);
+        } else {
+            let start = self.offset();
+            let mut depth = 0;
+            while self.peek().is_some() && (depth > 0 || !is_column_end(self, 0)) {
+                if self.is_punct('(') {
+                    depth += 1;
+                } else if self.is_punct(')') {
+                    depth -= 1;
+                }
+                self.pos += 1;
+            }
+            column.expression = Some(self.source(start, self.offset()));
+        }
+
+        if self.eat_keyword("COLLATE") {
+            column.collation = Some(self.name()?);
+        }
+        column.order = self.sort_order();
+
+        Ok(column)
+    }
+
+    fn is_table_constraint(&self) -> bool {
+        ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|k| self.is_keyword(k))
+    }
+
+    fn table_constraint(&mut self, table: &mut TableDefinition) -> Result<(), SQLiteError> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.name()?;
+        }
+
+        if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            table.primary_key = self.indexed_columns()?;
+            table.key_constraints.push(table.primary_key.clone());
+            self.eat_keyword("AUTOINCREMENT");
+            self.conflict_clause()
+        } else if self.eat_keyword("UNIQUE") {
+            let columns = self.indexed_columns()?;
+            table.key_constraints.push(columns);
+            self.conflict_clause()
+        } else if self.eat_keyword("CHECK") {
+            self.parenthesized().map(|_| ())
+        } else if self.eat_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            self.parenthesized()?;
+            self.foreign_key_clause()
+        } else {
+            Err(self.error("table constraint"))
+        }
+    }
+
+    fn foreign_key_clause(&mut self) -> Result<(), SQLiteError> {
+        self.expect_keyword("REFERENCES")?;
+        self.name()?;
+        if self.is_punct('(') {
+            self.parenthesized()?;
+        }
+
+        loop {
+            if self.eat_keyword("ON") {
+                if !(self.eat_keyword("DELETE") || self.eat_keyword("UPDATE")) {
+                    return Err(self.error("DELETE or UPDATE"));
+                }
+                if self.eat_keyword("SET") {
+                    self.name()?;
+                } else if self.eat_keyword("NO") {
+                    self.expect_keyword("ACTION")?;
+                } else {
+                    self.name()?;
+                }
+            } else if self.eat_keyword("MATCH") {
+                self.name()?;
+            } else if self.is_keyword("DEFERRABLE")
+                || (self.is_keyword("NOT") && self.is_keyword_at(1, "DEFERRABLE"))
+            {
+                self.eat_keyword("NOT");
+                self.expect_keyword("DEFERRABLE")?;
+                if self.eat_keyword("INITIALLY") {
+                    self.name()?;
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn type_name(&mut self) -> Result<Option<String>, SQLiteError> {
+        let mut words = Vec::new();
+        while let Some(TokenKind::Word(w)) | Some(TokenKind::Name(w)) = self.peek().map(|t| &t.kind)
+        {
+            if COLUMN_CONSTRAINTS.iter().any(|k| w.eq_ignore_ascii_case(k)) {
+                break;
+            }
+            words.push(w.clone());
+            self.pos += 1;
+        }
+
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let mut type_name = words.join(" ");
+        if self.is_punct('(') {
+            let size = self.parenthesized()?;
+            type_name.push('(');
+            type_name.push_str(&size);
+            type_name.push(')');
+        }
+
+        Ok(Some(type_name))
+    }
+
+    /// Column and the order of the primary key, if it's declared on the column
+    fn column_definition(&mut self) -> Result<(ColumnDefinition, Option<SortOrder>), SQLiteError> {
+        let mut column = ColumnDefinition {
+            name: self.name()?,
+            declared_type: self.type_name()?,
+            ..ColumnDefinition::default()
+        };
+        let mut key_order = None;
+
+        loop {
+            if self.eat_keyword("CONSTRAINT") {
+                self.name()?;
+            }
+
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                key_order = Some(self.sort_order());
+                self.conflict_clause()?;
+                column.autoincrement = self.eat_keyword("AUTOINCREMENT");
+            } else if self.eat_keyword("NOT") {
+                self.expect_keyword("NULL")?;
+                self.conflict_clause()?;
+                column.not_null = true;
+            } else if self.eat_keyword("NULL") {
+                self.conflict_clause()?;
+            } else if self.eat_keyword("UNIQUE") {
+                self.conflict_clause()?;
+                column.unique = true;
+            } else if self.eat_keyword("CHECK") {
+                self.parenthesized()?;
+            } else if self.eat_keyword("DEFAULT") {
+                column.default = Some(self.default_value()?);
+            } else if self.eat_keyword("COLLATE") {
+                column.collation = Some(self.name()?);
+            } else if self.is_keyword("REFERENCES") {
+                self.foreign_key_clause()?;
+            } else if self.is_keyword("GENERATED") || self.is_keyword("AS") {
+                if self.eat_keyword("GENERATED") {
+                    self.expect_keyword("ALWAYS")?;
+                }
+                self.expect_keyword("AS")?;
+                self.parenthesized()?;
+                column.generated = if self.eat_keyword("STORED") {
+                    Some(Generated::Stored)
+                } else {
+                    self.eat_keyword("VIRTUAL");
+                    Some(Generated::Virtual)
+                };
+            } else {
+                return Ok((column, key_order));
+            }
+        }
+    }
+
+    fn default_value(&mut self) -> Result<DefaultValue, SQLiteError> {
+        if !self.is_punct('(') {
+            return self.literal();
+        }
+
+        // constant wrapped in parenthesis, however many, is still a constant
+        let start = self.pos;
+        let mut depth = 0;
+        while self.eat_punct('(') {
+            depth += 1;
+        }
+        if let Ok(literal) = self.literal() {
+            if (0..depth).all(|_| self.eat_punct(')')) {
+                return Ok(literal);
+            }
+        }
+        self.pos = start;
+
+        Ok(DefaultValue::Expression(self.parenthesized()?))
+    }
+
+    fn literal(&mut self) -> Result<DefaultValue, SQLiteError> {
+        let negative = if self.eat_punct('-') {
+            true
+        } else {
+            self.eat_punct('+');
+            false
+        };
+
+        let token = self.peek().cloned().ok_or_else(|| self.error("value"))?;
+        let value = match token.kind {
+            TokenKind::Number(n) => number_value(&n, negative),
+            _ if negative => return Err(self.error("number")),
+            TokenKind::Str(s) | TokenKind::Name(s) => DefaultValue::Text(s),
+            TokenKind::Blob(b) => DefaultValue::Blob(b),
+            TokenKind::Word(w) if w.eq_ignore_ascii_case("NULL") => DefaultValue::Null,
+            TokenKind::Word(w) if w.eq_ignore_ascii_case("TRUE") => DefaultValue::Integer {
+                value: 1,
+                text: None,
+            },
+            TokenKind::Word(w) if w.eq_ignore_ascii_case("FALSE") => DefaultValue::Integer {
+                value: 0,
+                text: None,
+            },
+            TokenKind::Word(w)
+                if ["CURRENT_TIME", "CURRENT_DATE", "CURRENT_TIMESTAMP"]
+                    .iter()
+                    .any(|k| w.eq_ignore_ascii_case(k)) =>
+            {
+                DefaultValue::Expression(w)
+            }
+            // bare identifiers are taken as strings
+            TokenKind::Word(w) => DefaultValue::Text(w),
+            TokenKind::Punct(_) => return Err(self.error("value")),
+        };
+        self.pos += 1;
+
+        Ok(value)
+    }
+}
+
+fn number_value(number: &str, negative: bool) -> DefaultValue {
+    let sign = if negative { "-" } else { "" };
+    let text = format!("{}{}", sign, number);
+
+    let hex = number
+        .get(..2)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("0x"));
+    let unsigned = if hex {
+        u64::from_str_radix(&number[2..], 16).ok()
+    } else {
+        number.parse::<u64>().ok()
+    };
+    let integer = if hex {
+        unsigned.map(|v| {
+            if negative {
+                (v as i64).wrapping_neg()
+            } else {
+                v as i64
+            }
+        })
+    } else {
+        text.parse::<i64>().ok()
+    };
+
+    match integer {
+        // SQLite keeps the value of small integers, not the literal
+        Some(value) if unsigned.is_some_and(|v| v <= i32::MAX as u64) => DefaultValue::Integer {
+            value,
+            text: Some(value.to_string()),
+        },
+        Some(value) => DefaultValue::Integer {
+            value,
+            text: Some(text),
+        },
+        None => DefaultValue::Real {
+            value: text.parse().unwrap_or(0.0),
+            text: Some(text),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_simple_table() {
+        let table =
+            parse_create_table("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT NOT NULL)")
+                .unwrap();
+
+        assert_eq!(table.name, "test");
+        assert_eq!(table.column_names().collect::<Vec<_>>(), vec!["id", "foo"]);
+        assert_eq!(table.columns[0].declared_type.as_deref(), Some("INTEGER"));
+        assert!(table.columns[0].primary_key);
+        assert!(table.columns[1].not_null);
+        assert_eq!(table.rowid_alias, Some(0));
+        assert!(!table.without_rowid);
+        assert!(!table.strict);
+    }
+
+    #[test]
+    fn parse_column_constraints() {
+        let table = parse_create_table(
+            "CREATE TABLE IF NOT EXISTS main.\"weird table\" (
+                -- comment
+                [id] integer CONSTRAINT pk PRIMARY KEY AUTOINCREMENT,
+                `name` VARCHAR ( 255 ) COLLATE NOCASE UNIQUE DEFAULT 'it''s',
+                price UNSIGNED BIG INT DEFAULT -42 CHECK (price > 0),
+                ratio REAL DEFAULT (1.5e3),
+                data BLOB DEFAULT x'CAFE',
+                created DEFAULT CURRENT_TIMESTAMP,
+                computed AS (price * ratio) /* virtual */,
+                stored GENERATED ALWAYS AS (price + 1) STORED,
+                parent INTEGER REFERENCES parent (id) ON DELETE SET NULL NOT DEFERRABLE NOT NULL,
+                flag BOOLEAN DEFAULT TRUE,
+                maximum DEFAULT 0x7fffffffffffffff,
+                expr DEFAULT (abs(-1))
+            )",
+        )
+        .unwrap();
+
+        assert_eq!(table.name, "weird table");
+        assert_eq!(table.rowid_alias, Some(0));
+        assert!(table.columns[0].autoincrement);
+
+        let name = &table.columns[1];
+        assert_eq!(name.name, "name");
+        assert_eq!(name.declared_type.as_deref(), Some("VARCHAR(255)"));
+        assert_eq!(name.collation.as_deref(), Some("NOCASE"));
+        assert!(name.unique);
+        assert_eq!(name.default, Some(DefaultValue::Text("it's".into())));
+
+        let price = &table.columns[2];
+        assert_eq!(price.declared_type.as_deref(), Some("UNSIGNED BIG INT"));
+        assert_eq!(
+            price.default,
+            Some(DefaultValue::Integer {
+                value: -42,
+                text: Some("-42".into())
+            })
+        );
+
+        assert_eq!(
+            table.columns[3].default,
+            Some(DefaultValue::Real {
+                value: 1500.0,
+                text: Some("1.5e3".into())
+            })
+        );
+        assert_eq!(
+            table.columns[4].default,
+            Some(DefaultValue::Blob(vec![0xca, 0xfe]))
+        );
+        assert_eq!(table.columns[5].declared_type, None);
+        assert_eq!(
+            table.columns[5].default,
+            Some(DefaultValue::Expression("CURRENT_TIMESTAMP".into()))
+        );
+        assert_eq!(table.columns[6].generated, Some(Generated::Virtual));
+        assert_eq!(table.columns[7].generated, Some(Generated::Stored));
+        assert!(table.columns[8].not_null);
+        assert_eq!(
+            table.columns[9].default,
+            Some(DefaultValue::Integer {
+                value: 1,
+                text: None
+            })
+        );
+        assert_eq!(
+            table.columns[10].default,
+            Some(DefaultValue::Integer {
+                value: i64::MAX,
+                text: Some("0x7fffffffffffffff".into())
+            })
+        );
+        assert_eq!(
+            table.columns[11].default,
+            Some(DefaultValue::Expression("abs(-1)".into()))
+        );
+        assert_eq!(table.stored_columns().count(), 11);
+    }
+
+    #[test]
+    fn parse_table_constraints() {
+        let table = parse_create_table(
+            "CREATE TABLE t (a TEXT, b INT, c, PRIMARY KEY (b DESC, a COLLATE nocase),
+            UNIQUE (c), FOREIGN KEY (c) REFERENCES other (x) ON UPDATE NO ACTION,
+            CHECK (a != b)) WITHOUT ROWID, STRICT",
+        )
+        .unwrap();
+
+        assert!(table.without_rowid);
+        assert!(table.strict);
+        assert_eq!(table.rowid_alias, None);
+        assert_eq!(
+            table.primary_key,
+            vec![
+                IndexedColumn {
+                    name: Some("b".into()),
+                    order: SortOrder::Desc,
+                    ..IndexedColumn::default()
+                },
+                IndexedColumn {
+                    name: Some("a".into()),
+                    collation: Some("nocase".into()),
+                    ..IndexedColumn::default()
+                },
+            ]
+        );
+        assert!(table.columns[0].primary_key);
+        assert!(table.columns[1].primary_key);
+        assert!(!table.columns[2].primary_key);
+    }
+
+    #[test]
+    fn rowid_alias_rules() {
+        let alias = |sql| parse_create_table(sql).unwrap().rowid_alias;
+
+        assert_eq!(
+            alias("CREATE TABLE t (a, id INTEGER, PRIMARY KEY (id DESC))"),
+            Some(1)
+        );
+        assert_eq!(alias("CREATE TABLE t (id INTEGER PRIMARY KEY DESC)"), None);
+        assert_eq!(alias("CREATE TABLE t (id INT PRIMARY KEY)"), None);
+        assert_eq!(
+            alias("CREATE TABLE t (id INTEGER, b, PRIMARY KEY (id, b))"),
+            None
+        );
+        assert_eq!(
+            alias("CREATE TABLE t (id INTEGER PRIMARY KEY) WITHOUT ROWID"),
+            None
+        );
+    }
+
+    #[test]
+    fn without_rowid_record_order() {
+        let order = |sql| parse_create_table(sql).unwrap().record_order();
+
+        assert_eq!(
+            order("CREATE TABLE t (a, b, c, PRIMARY KEY (c, a)) WITHOUT ROWID"),
+            vec![2, 0, 1]
+        );
+        assert_eq!(
+            order("CREATE TABLE t (a, b PRIMARY KEY, c AS (a + 1), d) WITHOUT ROWID"),
+            vec![1, 0, 3]
+        );
+        assert_eq!(
+            order("CREATE TABLE t (a, b, c, PRIMARY KEY (c, a))"),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn fail_on_expression_default_of_missing_column() {
+        let table = parse_create_table(
+            "CREATE TABLE t (a, b DEFAULT ((-1)), c DEFAULT (CURRENT_TIMESTAMP))",
+        )
+        .unwrap();
+        assert_eq!(
+            table.columns[1].default,
+            Some(DefaultValue::Integer {
+                value: -1,
+                text: Some("-1".into())
+            })
+        );
+
+        let row = table.row_values(None, vec![Some(Payload::I8(1))]);
+        assert!(matches!(row, Err(SQLiteError::DefaultValueError(_))));
+        let row = table.row_values(None, vec![Some(Payload::I8(1)), None, None]);
+        assert_eq!(row.unwrap(), vec![Some(Payload::I8(1)), None, None]);
+    }
+
+    #[test]
+    fn number_automatic_indexes() {
+        let table = parse_create_table(
+            "CREATE TABLE a (x UNIQUE, y PRIMARY KEY, z COLLATE NOCASE, UNIQUE(z), UNIQUE(X))",
+        )
+        .unwrap();
+        let names = |n| {
+            table.automatic_index(n).map(|key| {
+                key.iter()
+                    .map(|c| c.name.clone().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(names(1), Some(vec!["x".to_string()]));
+        assert_eq!(names(2), Some(vec!["y".to_string()]));
+        assert_eq!(names(3), Some(vec!["z".to_string()]));
+        assert_eq!(names(4), None);
+        assert_eq!(
+            table.collation_of(&table.key_constraints[2][0]),
+            Some("NOCASE")
+        );
+
+        let table =
+            parse_create_table("CREATE TABLE c (id INTEGER PRIMARY KEY, u UNIQUE)").unwrap();
+        assert_eq!(
+            table.automatic_index(1).unwrap()[0].name.as_deref(),
+            Some("u")
+        );
+
+        let table =
+            parse_create_table("CREATE TABLE b (k PRIMARY KEY, v UNIQUE) WITHOUT ROWID").unwrap();
+        assert_eq!(
+            table.automatic_index(2).unwrap()[0].name.as_deref(),
+            Some("v")
+        );
+    }
+
+    #[test]
+    fn parse_index() {
+        let index = parse_create_index(
+            "CREATE UNIQUE INDEX idx ON \"test\" (foo COLLATE NOCASE DESC, lower(bar), baz)
+            WHERE baz IS NOT NULL",
+        )
+        .unwrap();
+
+        assert_eq!(index.name, "idx");
+        assert_eq!(index.table, "test");
+        assert!(index.unique);
+        assert_eq!(
+            index.columns,
+            vec![
+                IndexedColumn {
+                    name: Some("foo".into()),
+                    collation: Some("NOCASE".into()),
+                    order: SortOrder::Desc,
+                    ..IndexedColumn::default()
+                },
+                IndexedColumn {
+                    expression: Some("lower(bar)".into()),
+                    ..IndexedColumn::default()
+                },
+                IndexedColumn {
+                    name: Some("baz".into()),
+                    ..IndexedColumn::default()
+                },
+            ]
+        );
+        assert_eq!(index.where_clause.as_deref(), Some("baz IS NOT NULL"));
+    }
+
+    #[test]
+    fn fail_on_garbage() {
+        assert!(parse_create_table("CREATE VIEW v AS SELECT 1").is_err());
+        assert!(parse_create_table("CREATE TABLE t (a").is_err());
+        assert!(parse_create_index("CREATE INDEX i ON t").is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter};
 
+use crate::model::TextEncoding;
+
 #[derive(thiserror::Error, Debug)]
 pub enum SQLiteError {
     #[error(transparent)]
@@ -10,6 +12,75 @@ pub enum SQLiteError {
 
     #[error("unknown text encoding `{0}`")]
     UnknownTextEncodingError(u32),
+
+    #[error("text is not valid {0:?}")]
+    TextDecodingError(TextEncoding),
+
+    /// Page number as stored in the database (1-based)
+    #[error("page `{0}` is out of the file bounds")]
+    PageOutOfBoundsError(u32),
+
+    #[error("overflow chain starting at page `{0}` ended before the whole payload was read")]
+    TruncatedOverflowError(u32),
+
+    #[error("payload of `{0}` bytes needs more overflow pages than the database has")]
+    PayloadSizeError(u64),
+
+    #[error("overflow page `{0}` is visited twice")]
+    OverflowLoopError(u32),
+
+    #[error("page `{0}` has unexpected type for this b-tree")]
+    UnexpectedPageTypeError(u32),
+
+    #[error("b-tree is deeper than allowed at page `{0}`")]
+    BTreeTooDeepError(u32),
+
+    #[error("invalid sqlite_schema entry: {0}")]
+    SchemaError(String),
+
+    #[error("can't parse SQL: {0}")]
+    SqlParsingError(String),
+
+    #[error("DEFAULT `{0}` is not a constant")]
+    DefaultValueError(String),
+
+    #[error("unknown collation `{0}`")]
+    UnknownCollationError(String),
+
+    #[error("key collations of the b-tree rooted at page `{0}` can't be resolved")]
+    KeyColumnsError(u32),
+
+    #[error("WAL header checksum doesn't match")]
+    WalHeaderChecksumError,
+
+    /// Frame number (1-based) with a wrong checksum or page number
+    #[error("WAL frame `{0}` failed verification")]
+    InvalidWalFrameError(u32),
+
+    #[error("there is no commit `{0}` in the WAL")]
+    WalCommitOutOfBoundsError(usize),
+
+    #[error("WAL page size `{0}` doesn't match the database page size")]
+    WalPageSizeError(u32),
+
+    #[error("journal page size `{0}` doesn't match the database page size")]
+    JournalPageSizeError(u32),
+
+    /// Offset of the page record in the journal
+    #[error("journal page record at offset `{0}` is truncated or has a wrong checksum")]
+    InvalidJournalPageError(usize),
+
+    #[error("freelist trunk page `{0}` is visited twice")]
+    FreelistLoopError(u32),
+}
+
+impl<'a> From<nom::error::Error<&'a [u8]>> for SQLiteError {
+    fn from(e: nom::error::Error<&'a [u8]>) -> Self {
+        SQLiteError::ParsingError(nom::error::Error {
+            code: e.code,
+            input: OwnedBytes(e.input.to_owned()),
+        })
+    }
 }
 
 /// Used so the error could outlive its input
@@ -24,8 +95,8 @@ impl From<Vec<u8>> for OwnedBytes {
 
 impl Display for OwnedBytes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.0.iter().fold(Ok(()), |result, byte| {
-            result.and_then(|_| writeln!(f, "{:X} ", byte))
-        })
+        self.0
+            .iter()
+            .try_for_each(|byte| writeln!(f, "{:X} ", byte))
     }
 }
@@ -0,0 +1,673 @@
+//! Structural consistency check of the whole database, along the lines of
+//! `PRAGMA integrity_check`.
+//!
+//! Every b-tree listed in the schema is walked from its root, checking the order of keys,
+//! the depth of leaves and the layout of each page. Overflow chains and the freelist
+//! are followed too, so in the end every page should have been referenced exactly once.
+
+use std::cmp::Ordering;
+
+use nom::Finish;
+
+use crate::chain::{FreelistTrunks, OverflowChain};
+use crate::compare::{compare_records, KeyColumn};
+use crate::error::SQLiteError;
+use crate::model::{Page, PageLayout, PointerMapEntry, RowValues};
+use crate::parser::{pointer_map_page, HEADER_SIZE};
+use crate::varint::varint_len;
+use crate::{to_pageno, Reader};
+
+/// Smallest cell SQLite allocates, shorter cells are padded
+const MIN_CELL_SIZE: usize = 4;
+const FREEBLOCK_HEADER_SIZE: usize = 4;
+/// Freeblocks closer than this are merged, the gap would be a fragment
+const MAX_FRAGMENT_SIZE: usize = 3;
+
+#[derive(Debug)]
+pub enum IntegrityProblem {
+    /// Page number is 0 or past the database size.
+    /// `referenced_by` is the page holding the reference, 0 for the database header.
+    PageOutOfRange { page_no: u32, referenced_by: u32 },
+    /// Page is referenced once more, after it was reached already
+    PageReferencedTwice { page_no: u32, referenced_by: u32 },
+    /// Page isn't a part of any b-tree, overflow chain or the freelist
+    PageNeverUsed { page_no: u32 },
+    /// Page can't be read as what it's referenced as, its children aren't checked
+    UnreadablePage { page_no: u32, error: SQLiteError },
+    /// Key of the cell isn't greater than the previous one
+    /// or is out of the range set by the parent page
+    KeyOutOfOrder { page_no: u32, cell: usize },
+    /// Leaf is at a different depth than the first leaf of the same b-tree
+    UnevenDepth {
+        page_no: u32,
+        depth: usize,
+        expected: usize,
+    },
+    /// Cell overlaps another cell or a freeblock, or lies outside of the content area
+    CellOverlap { page_no: u32, offset: usize },
+    /// Freeblock is out of order, too small, too close to the next one or past the page end
+    InvalidFreeblock { page_no: u32, offset: usize },
+    /// Bytes of the content area not covered by cells or freeblocks differ from the header count
+    FragmentCount {
+        page_no: u32,
+        expected: u8,
+        actual: usize,
+    },
+    /// Overflow chain has a different number of pages than the payload size requires.
+    /// `page_no` is the b-tree page holding the cell.
+    OverflowChainLength {
+        page_no: u32,
+        cell: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// Pages found on the freelist differ from `total_freelist_pages` in the header
+    FreelistCount { expected: u32, actual: u32 },
+    /// Pointer-map entry of the page doesn't tell how the page was actually reached
+    PointerMapMismatch {
+        page_no: u32,
+        expected: PointerMapEntry,
+        actual: Option<PointerMapEntry>,
+    },
+    /// sqlite_schema can't be read, so no other b-tree is checked
+    /// and their pages are reported as never used
+    UnreadableSchema { error: SQLiteError },
+}
+
+/// Key of a cell: rowid in table b-trees, the whole record in index b-trees.
+/// `None` when the record spills onto overflow pages which can't be read.
+enum Key<'a> {
+    Rowid(i64),
+    Record(Option<RowValues<'a>>),
+}
+
+/// What every page of the same b-tree must agree on
+struct BTree<'c> {
+    /// `None` when the order of records is unknown, then only rowids are checked
+    columns: Option<&'c [KeyColumn]>,
+    /// Type of the root page, table or index
+    is_table: Option<bool>,
+    /// Depth of the first leaf reached
+    leaf_depth: Option<usize>,
+}
+
+/// Parts of a b-tree page the check needs, regardless of the page type
+struct PageSummary<'a> {
+    is_table: bool,
+    is_leaf: bool,
+    header_size: usize,
+    first_freeblock_offset: Option<u16>,
+    cell_content_offset: u32,
+    no_fragmented_bytes: u8,
+    /// Offset and size of every cell, in the cell pointer array order
+    cells: Vec<(usize, usize)>,
+    keys: Vec<Key<'a>>,
+    /// Left child of every cell followed by the rightmost pointer, empty for leaves
+    children: Vec<u32>,
+    /// Cell, first overflow page and the number of pages the chain should have
+    overflows: Vec<(usize, u32, usize)>,
+}
+
+/// Cell size on the page: varints, the local payload and the first overflow page number
+fn cell_size(varints: &[u64], local_size: usize, overflow_page_no: Option<u32>) -> usize {
+    let varints: usize = varints.iter().map(|&v| varint_len(v)).sum();
+    let overflow = overflow_page_no.map_or(0, |_| 4);
+    (varints + local_size + overflow).max(MIN_CELL_SIZE)
+}
+
+fn overflow_pages(layout: &PageLayout, payload_size: u64, local_size: usize) -> usize {
+    (payload_size as usize - local_size).div_ceil(layout.overflow_payload_size())
+}
+
+pub(crate) struct IntegrityCheck<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    layout: PageLayout,
+    /// Number of references to every page, indexed by the page number
+    references: Vec<u32>,
+    /// Pointer-map entry matching the first reference to every page, indexed by the page number
+    expected_ptrmap: Vec<Option<PointerMapEntry>>,
+    problems: Vec<IntegrityProblem>,
+}
+
+impl<'a, S: AsRef<[u8]>> IntegrityCheck<'a, S> {
+    pub(crate) fn new(reader: &'a Reader<S>) -> Self {
+        let page_count = reader.page_count() as usize;
+
+        IntegrityCheck {
+            reader,
+            layout: PageLayout::from(&reader.header),
+            references: vec![0; page_count + 1],
+            expected_ptrmap: vec![None; page_count + 1],
+            problems: vec![],
+        }
+    }
+
+    pub(crate) fn run(mut self) -> Vec<IntegrityProblem> {
+        self.check_btree(1, 0, Some(&[]));
+        match self.reader.schema() {
+            Ok(schema) => {
+                for entry in schema.entries.iter() {
+                    if let Some(root_page_no) = entry.root_page.filter(|&p| p != 0) {
+                        let columns = schema.key_columns(root_page_no).ok();
+                        self.check_btree(root_page_no, 1, columns.as_deref());
+                    }
+                }
+            }
+            Err(error) => self
+                .problems
+                .push(IntegrityProblem::UnreadableSchema { error }),
+        }
+        self.check_freelist();
+        self.check_unused_pages();
+        self.check_pointer_map();
+
+        self.problems
+    }
+
+    /// Counts the reference, `false` if the page doesn't exist or was reached before.
+    /// `ptrmap` is the pointer-map entry the page should have when reached this way.
+    fn reference(&mut self, page_no: u32, referenced_by: u32, ptrmap: PointerMapEntry) -> bool {
+        let references = match self.references.get_mut(page_no as usize) {
+            Some(references) if page_no != 0 => references,
+            _ => {
+                self.problems.push(IntegrityProblem::PageOutOfRange {
+                    page_no,
+                    referenced_by,
+                });
+                return false;
+            }
+        };
+
+        *references += 1;
+        if *references > 1 {
+            self.problems.push(IntegrityProblem::PageReferencedTwice {
+                page_no,
+                referenced_by,
+            });
+        }
+
+        if *references == 1 {
+            self.expected_ptrmap[page_no as usize] = Some(ptrmap);
+        }
+
+        *references == 1
+    }
+
+    fn check_btree(
+        &mut self,
+        root_page_no: u32,
+        referenced_by: u32,
+        columns: Option<&[KeyColumn]>,
+    ) {
+        let mut tree = BTree {
+            columns,
+            is_table: None,
+            leaf_depth: None,
+        };
+        self.check_page(root_page_no, referenced_by, 0, (None, None), &mut tree);
+    }
+
+    /// Checks the page and its subtree. Keys must be within the bounds, the lower one is
+    /// exclusive, the upper one is inclusive for rowids as interior table keys are copies
+    /// of the largest rowid on the left.
+    fn check_page(
+        &mut self,
+        page_no: u32,
+        referenced_by: u32,
+        depth: usize,
+        bounds: (Option<&Key>, Option<&Key>),
+        tree: &mut BTree,
+    ) {
+        let ptrmap = match depth {
+            0 => PointerMapEntry::RootPage,
+            _ => PointerMapEntry::BTree {
+                parent_page_no: referenced_by,
+            },
+        };
+        if !self.reference(page_no, referenced_by, ptrmap) {
+            return;
+        }
+
+        let page = match self.summarize(page_no) {
+            Ok(page) if *tree.is_table.get_or_insert(page.is_table) == page.is_table => page,
+            Ok(_) => {
+                let error = SQLiteError::UnexpectedPageTypeError(page_no);
+                self.problems
+                    .push(IntegrityProblem::UnreadablePage { page_no, error });
+                return;
+            }
+            Err(error) => {
+                self.problems
+                    .push(IntegrityProblem::UnreadablePage { page_no, error });
+                return;
+            }
+        };
+
+        self.check_layout(page_no, &page);
+        self.check_keys(page_no, &page, bounds, tree.columns);
+        for &(cell, overflow_page_no, expected) in page.overflows.iter() {
+            self.check_overflow_chain(page_no, cell, overflow_page_no, expected);
+        }
+
+        if page.is_leaf {
+            let expected = *tree.leaf_depth.get_or_insert(depth);
+            if depth != expected {
+                self.problems.push(IntegrityProblem::UnevenDepth {
+                    page_no,
+                    depth,
+                    expected,
+                });
+            }
+            return;
+        }
+
+        // each child is bounded by the keys around its pointer
+        for (i, &child_page_no) in page.children.iter().enumerate() {
+            let lower = match i {
+                0 => bounds.0,
+                _ => page.keys.get(i - 1),
+            };
+            let upper = page.keys.get(i).or(bounds.1);
+            self.check_page(child_page_no, page_no, depth + 1, (lower, upper), tree);
+        }
+    }
+
+    fn summarize(&self, page_no: u32) -> Result<PageSummary<'a>, SQLiteError> {
+        let reader = self.reader;
+        let page = reader.get_local_page(to_pageno(page_no)?)?;
+        let layout = &self.layout;
+
+        let summary = match page {
+            Page::InteriorTable(p) => PageSummary {
+                is_table: true,
+                is_leaf: false,
+                header_size: 12,
+                first_freeblock_offset: p.header.first_freeblock_offset,
+                cell_content_offset: p.header.cell_content_offset.real_offset(),
+                no_fragmented_bytes: p.header.no_fragmented_bytes,
+                cells: p
+                    .cell_pointers
+                    .iter()
+                    .zip(p.cells.iter())
+                    .map(|(&ptr, c)| (ptr as usize, 4 + varint_len(c.integer_key as u64)))
+                    .collect(),
+                keys: p.cells.iter().map(|c| Key::Rowid(c.integer_key)).collect(),
+                children: p
+                    .cells
+                    .iter()
+                    .map(|c| c.left_child_page_no)
+                    .chain(Some(p.header.rightmost_pointer))
+                    .collect(),
+                overflows: vec![],
+            },
+            Page::LeafTable(p) => PageSummary {
+                is_table: true,
+                is_leaf: true,
+                header_size: 8,
+                first_freeblock_offset: p.header.first_freeblock_offset,
+                cell_content_offset: p.header.cell_content_offset.real_offset(),
+                no_fragmented_bytes: p.header.no_fragmented_bytes,
+                cells: p
+                    .cell_pointers
+                    .iter()
+                    .zip(p.cells.iter())
+                    .map(|(&ptr, c)| {
+                        let varints = [c.payload_size, c.rowid as u64];
+                        let size = cell_size(&varints, c.local_payload.len(), c.overflow_page_no);
+                        (ptr as usize, size)
+                    })
+                    .collect(),
+                keys: p.cells.iter().map(|c| Key::Rowid(c.rowid)).collect(),
+                children: vec![],
+                overflows: p
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| {
+                        let expected =
+                            overflow_pages(layout, c.payload_size, c.local_payload.len());
+                        Some((i, c.overflow_page_no?, expected))
+                    })
+                    .collect(),
+            },
+            Page::InteriorIndex(mut p) => PageSummary {
+                is_table: false,
+                is_leaf: false,
+                header_size: 12,
+                first_freeblock_offset: p.header.first_freeblock_offset,
+                cell_content_offset: p.header.cell_content_offset.real_offset(),
+                no_fragmented_bytes: p.header.no_fragmented_bytes,
+                cells: p
+                    .cell_pointers
+                    .iter()
+                    .zip(p.cells.iter())
+                    .map(|(&ptr, c)| {
+                        let size = 4 + cell_size(
+                            &[c.payload_size],
+                            c.local_payload.len(),
+                            c.overflow_page_no,
+                        );
+                        (ptr as usize, size)
+                    })
+                    .collect(),
+                overflows: p
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| {
+                        let expected =
+                            overflow_pages(layout, c.payload_size, c.local_payload.len());
+                        Some((i, c.overflow_page_no?, expected))
+                    })
+                    .collect(),
+                children: p
+                    .cells
+                    .iter()
+                    .map(|c| c.left_child_page_no)
+                    .chain(Some(p.header.rightmost_pointer))
+                    .collect(),
+                keys: p
+                    .cells
+                    .iter_mut()
+                    .map(|c| {
+                        let resolved = reader.resolve_interior_index_cell(c).is_ok();
+                        let values = std::mem::take(&mut c.payload.column_values);
+                        Key::Record(resolved.then_some(values))
+                    })
+                    .collect(),
+            },
+            Page::LeafIndex(mut p) => PageSummary {
+                is_table: false,
+                is_leaf: true,
+                header_size: 8,
+                first_freeblock_offset: p.header.first_freeblock_offset,
+                cell_content_offset: p.header.cell_content_offset.real_offset(),
+                no_fragmented_bytes: p.header.no_fragmented_bytes,
+                cells: p
+                    .cell_pointers
+                    .iter()
+                    .zip(p.cells.iter())
+                    .map(|(&ptr, c)| {
+                        let size =
+                            cell_size(&[c.payload_size], c.local_payload.len(), c.overflow_page_no);
+                        (ptr as usize, size)
+                    })
+                    .collect(),
+                overflows: p
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| {
+                        let expected =
+                            overflow_pages(layout, c.payload_size, c.local_payload.len());
+                        Some((i, c.overflow_page_no?, expected))
+                    })
+                    .collect(),
+                children: vec![],
+                keys: p
+                    .cells
+                    .iter_mut()
+                    .map(|c| {
+                        let resolved = reader.resolve_leaf_index_cell(c).is_ok();
+                        let values = std::mem::take(&mut c.payload.column_values);
+                        Key::Record(resolved.then_some(values))
+                    })
+                    .collect(),
+            },
+            Page::PointerMap(_) => return Err(SQLiteError::UnexpectedPageTypeError(page_no)),
+        };
+
+        Ok(summary)
+    }
+
+    /// Compares keys of the same b-tree, `None` if either key or the record order is unknown.
+    fn compare(&self, a: &Key, b: &Key, columns: Option<&[KeyColumn]>) -> Option<Ordering> {
+        match (a, b, columns) {
+            (Key::Rowid(a), Key::Rowid(b), _) => Some(a.cmp(b)),
+            (Key::Record(Some(a)), Key::Record(Some(b)), Some(columns)) => Some(compare_records(
+                a,
+                b,
+                columns,
+                self.reader.header.db_text_encoding,
+            )),
+            _ => None,
+        }
+    }
+
+    fn check_keys(
+        &mut self,
+        page_no: u32,
+        page: &PageSummary,
+        bounds: (Option<&Key>, Option<&Key>),
+        columns: Option<&[KeyColumn]>,
+    ) {
+        for (cell, key) in page.keys.iter().enumerate() {
+            let previous = match cell {
+                0 => bounds.0,
+                _ => page.keys.get(cell - 1),
+            };
+            let after_previous = previous
+                .and_then(|previous| self.compare(key, previous, columns))
+                .map_or(true, |o| o == Ordering::Greater);
+            let within_upper = bounds
+                .1
+                .and_then(|upper| self.compare(key, upper, columns))
+                .map_or(true, |o| {
+                    o == Ordering::Less || (page.is_table && o == Ordering::Equal)
+                });
+
+            if !after_previous || !within_upper {
+                self.problems
+                    .push(IntegrityProblem::KeyOutOfOrder { page_no, cell });
+            }
+        }
+    }
+
+    /// Cells, freeblocks and fragments must account for the whole content area, once.
+    fn check_layout(&mut self, page_no: u32, page: &PageSummary) {
+        let bytes = match to_pageno(page_no).and_then(|pageno| self.reader.page_bytes(pageno)) {
+            Ok(bytes) => &bytes[..self.layout.usable_size],
+            Err(_) => return,
+        };
+        let header_offset = if page_no == 1 { HEADER_SIZE } else { 0 };
+        let pointers_end = header_offset + page.header_size + page.cells.len() * 2;
+        let content_start = (page.cell_content_offset as usize)
+            .min(bytes.len())
+            .max(pointers_end);
+
+        let mut used = Vec::with_capacity(page.cells.len());
+        for &(offset, size) in page.cells.iter() {
+            if offset < content_start || offset + size > bytes.len() {
+                self.problems
+                    .push(IntegrityProblem::CellOverlap { page_no, offset });
+            } else {
+                used.push((offset, offset + size, false));
+            }
+        }
+
+        // freeblocks are kept in ascending order, adjacent ones are merged
+        let mut offset = page.first_freeblock_offset.unwrap_or(0) as usize;
+        let mut min_offset = content_start;
+        while offset != 0 {
+            let header = bytes.get(offset..offset + FREEBLOCK_HEADER_SIZE);
+            let (next, size) = match header {
+                Some(h) if offset >= min_offset => (
+                    u16::from_be_bytes([h[0], h[1]]) as usize,
+                    u16::from_be_bytes([h[2], h[3]]) as usize,
+                ),
+                _ => {
+                    self.problems
+                        .push(IntegrityProblem::InvalidFreeblock { page_no, offset });
+                    break;
+                }
+            };
+            let too_close = next != 0 && next <= offset + size + MAX_FRAGMENT_SIZE;
+            if size < FREEBLOCK_HEADER_SIZE || offset + size > bytes.len() || too_close {
+                self.problems
+                    .push(IntegrityProblem::InvalidFreeblock { page_no, offset });
+                break;
+            }
+
+            used.push((offset, offset + size, true));
+            min_offset = offset + size;
+            offset = next;
+        }
+
+        used.sort_unstable();
+        let mut covered = 0;
+        let mut end = content_start;
+        for &(start, stop, is_freeblock) in used.iter() {
+            if start < end {
+                let problem = match is_freeblock {
+                    true => IntegrityProblem::InvalidFreeblock {
+                        page_no,
+                        offset: start,
+                    },
+                    false => IntegrityProblem::CellOverlap {
+                        page_no,
+                        offset: start,
+                    },
+                };
+                self.problems.push(problem);
+            }
+            covered += stop.saturating_sub(start.max(end));
+            end = end.max(stop);
+        }
+
+        let fragmented = bytes.len() - content_start - covered;
+        if fragmented != page.no_fragmented_bytes as usize {
+            self.problems.push(IntegrityProblem::FragmentCount {
+                page_no,
+                expected: page.no_fragmented_bytes,
+                actual: fragmented,
+            });
+        }
+    }
+
+    fn check_overflow_chain(&mut self, page_no: u32, cell: usize, first: u32, expected: usize) {
+        let mut actual = 0;
+        let mut referenced_by = page_no;
+
+        for next in OverflowChain::new(self.reader, first) {
+            if actual > expected {
+                break;
+            }
+            let ptrmap = match actual {
+                0 => PointerMapEntry::Overflow1 {
+                    parent_page_no: referenced_by,
+                },
+                _ => PointerMapEntry::Overflow2 {
+                    parent_page_no: referenced_by,
+                },
+            };
+            if !self.reference(next, referenced_by, ptrmap) {
+                break;
+            }
+            actual += 1;
+            referenced_by = next;
+        }
+
+        if actual != expected {
+            self.problems.push(IntegrityProblem::OverflowChainLength {
+                page_no,
+                cell,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    fn check_freelist(&mut self) {
+        let header = &self.reader.header;
+        let expected = header.total_freelist_pages;
+        let mut actual = 0;
+        let mut referenced_by = 0;
+
+        for (trunk_page_no, trunk) in FreelistTrunks::new(self.reader) {
+            if !self.reference(trunk_page_no, referenced_by, PointerMapEntry::FreePage) {
+                break;
+            }
+            actual += 1;
+
+            let trunk = match trunk {
+                Ok(trunk) => trunk,
+                Err(error) => {
+                    self.problems.push(IntegrityProblem::UnreadablePage {
+                        page_no: trunk_page_no,
+                        error,
+                    });
+                    break;
+                }
+            };
+
+            for leaf_page_no in trunk.leaf_page_nos {
+                self.reference(leaf_page_no, trunk_page_no, PointerMapEntry::FreePage);
+                actual += 1;
+            }
+            referenced_by = trunk_page_no;
+        }
+
+        if actual != expected {
+            self.problems
+                .push(IntegrityProblem::FreelistCount { expected, actual });
+        }
+    }
+
+    /// Pointer-map pages and the lock-byte page are never referenced, but they're in use.
+    fn check_unused_pages(&mut self) {
+        let header = &self.reader.header;
+        let lock_byte_page_no = header.lock_byte_page_no();
+
+        for page_no in 1..=self.reader.page_count() {
+            if self.references[page_no as usize] == 0
+                && page_no != lock_byte_page_no
+                && !header.is_ptrmap_page(page_no)
+            {
+                self.problems
+                    .push(IntegrityProblem::PageNeverUsed { page_no });
+            }
+        }
+    }
+
+    /// Entries of auto-vacuumed databases must match how the pages were reached by the walk.
+    /// Pages which weren't reached are reported as never used already.
+    fn check_pointer_map(&mut self) {
+        for ptrmap_page_no in self.reader.ptrmap_page_nos() {
+            let ptrmap = to_pageno(ptrmap_page_no)
+                .and_then(|pageno| self.reader.page_bytes(pageno))
+                .and_then(|bytes| {
+                    let (_, ptrmap) =
+                        pointer_map_page(&bytes[..self.layout.usable_size]).finish()?;
+                    Ok(ptrmap)
+                });
+            let ptrmap = match ptrmap {
+                Ok(ptrmap) => ptrmap,
+                Err(error) => {
+                    self.problems.push(IntegrityProblem::UnreadablePage {
+                        page_no: ptrmap_page_no,
+                        error,
+                    });
+                    continue;
+                }
+            };
+
+            for (page_no, actual) in (ptrmap_page_no + 1..).zip(ptrmap.entries) {
+                let expected = self
+                    .expected_ptrmap
+                    .get(page_no as usize)
+                    .copied()
+                    .flatten();
+                match expected {
+                    Some(expected) if Some(expected) != actual => {
+                        self.problems.push(IntegrityProblem::PointerMapMismatch {
+                            page_no,
+                            expected,
+                            actual,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
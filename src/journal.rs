@@ -0,0 +1,312 @@
+//! Rollback journal, the `-journal` file holding original content of the pages
+//! changed by a transaction which is in progress or was interrupted.
+//!
+//! The journal is a sequence of segments, each starting with a header padded to the sector size
+//! and followed by page records: page number, original page image and a checksum.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapOptions};
+use nom::bytes::complete::{tag, take};
+use nom::number::complete::be_u32;
+use nom::sequence::Tuple;
+use nom::{Finish, IResult};
+
+use crate::error::SQLiteError;
+
+pub const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// Page count of a journal which was never synced, records are counted by the file size instead
+pub const JOURNAL_PAGE_COUNT_UNKNOWN: u32 = 0xffffffff;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct JournalHeader {
+    /// Number of page records in the segment
+    pub page_count: u32,
+    /// Initial value of the page record checksums
+    pub nonce: u32,
+    /// Database size in pages before the transaction
+    pub initial_db_size: u32,
+    /// Header is padded to this size
+    pub sector_size: u32,
+    pub page_size: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct JournalPage<'a> {
+    pub page_no: u32,
+    /// Page image as it was before the transaction
+    pub page_bytes: &'a [u8],
+    pub checksum: u32,
+}
+
+impl JournalPage<'_> {
+    /// Checksum is the nonce plus every 200th byte of the page, counting from the end.
+    pub fn is_valid(&self, nonce: u32) -> bool {
+        let checksum = (1..)
+            .map(|n| self.page_bytes.len() as isize - n * 200)
+            .take_while(|&i| i > 0)
+            .fold(nonce, |sum, i| {
+                sum.wrapping_add(self.page_bytes[i as usize] as u32)
+            });
+
+        checksum == self.checksum
+    }
+}
+
+pub fn journal_header(i: &[u8]) -> IResult<&[u8], JournalHeader> {
+    let (i, _) = tag(JOURNAL_MAGIC)(i)?;
+    let (i, (page_count, nonce, initial_db_size)) = (be_u32, be_u32, be_u32).parse(i)?;
+    let (i, (sector_size, page_size)) = (be_u32, be_u32).parse(i)?;
+
+    Ok((
+        i,
+        JournalHeader {
+            page_count,
+            nonce,
+            initial_db_size,
+            sector_size,
+            page_size,
+        },
+    ))
+}
+
+pub fn journal_page(page_size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], JournalPage<'_>> {
+    move |i| {
+        let (i, (page_no, page_bytes, checksum)) = (be_u32, take(page_size), be_u32).parse(i)?;
+
+        Ok((
+            i,
+            JournalPage {
+                page_no,
+                page_bytes,
+                checksum,
+            },
+        ))
+    }
+}
+
+/// A journal needs to be rolled back before the database is read, unless the transaction
+/// is still in progress, which can't be told from the files alone.
+/// Committed journals are deleted, truncated or have their header zeroed.
+pub fn is_hot_journal(journal: &[u8]) -> bool {
+    journal.first().is_some_and(|&b| b != 0)
+}
+
+pub struct JournalReader<S: AsRef<[u8]>> {
+    buf: S,
+    /// Header of the first segment
+    pub header: JournalHeader,
+}
+
+impl JournalReader<Mmap> {
+    /// Open a rollback journal by memory mapping it.
+    pub fn open_mmap<P: AsRef<Path>>(journal: P) -> Result<JournalReader<Mmap>, SQLiteError> {
+        let file_read = File::open(journal)?;
+        let mmap = unsafe { MmapOptions::new().map(&file_read) }?;
+        JournalReader::from_source(mmap)
+    }
+}
+
+impl JournalReader<Vec<u8>> {
+    /// Open a rollback journal by loading it into memory.
+    pub fn open_readfile<P: AsRef<Path>>(
+        journal: P,
+    ) -> Result<JournalReader<Vec<u8>>, SQLiteError> {
+        let buf: Vec<u8> = std::fs::read(journal)?;
+        JournalReader::from_source(buf)
+    }
+}
+
+impl<S: AsRef<[u8]>> JournalReader<S> {
+    /// Open a rollback journal from anything that implements AsRef<[u8]>
+    pub fn from_source(buf: S) -> Result<JournalReader<S>, SQLiteError> {
+        let (_, header) = journal_header(buf.as_ref()).finish()?;
+
+        Ok(JournalReader { buf, header })
+    }
+
+    pub fn is_hot(&self) -> bool {
+        is_hot_journal(self.buf.as_ref())
+    }
+
+    /// Page records of all the segments in the journal order.
+    /// The walk ends with an error at the first record with a wrong checksum,
+    /// which is where SQLite stops rolling back too.
+    pub fn pages(&self) -> JournalPages<'_> {
+        JournalPages {
+            buf: self.buf.as_ref(),
+            offset: 0,
+            record_offset: 0,
+            segment: None,
+            done: false,
+        }
+    }
+}
+
+/// Database as it was before the transaction, rolled back from the journal
+pub(crate) struct JournalSnapshot<S: AsRef<[u8]>> {
+    journal: JournalReader<S>,
+    /// Page number to the offset of the page record holding its original image
+    pages: HashMap<u32, usize>,
+    /// Record with a wrong checksum which ended the rollback early
+    pub(crate) error: Option<SQLiteError>,
+}
+
+impl<S: AsRef<[u8]>> JournalSnapshot<S> {
+    /// Only the first record of a page holds the original, later ones are ignored.
+    pub(crate) fn new(journal: JournalReader<S>) -> Self {
+        let mut pages = HashMap::new();
+        let mut error = None;
+
+        let mut records = journal.pages();
+        while let Some(page) = records.next() {
+            match page {
+                Ok(page) => {
+                    pages.entry(page.page_no).or_insert(records.record_offset);
+                }
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        JournalSnapshot {
+            journal,
+            pages,
+            error,
+        }
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.journal.header.page_size as usize
+    }
+
+    /// Database size in pages before the transaction
+    pub(crate) fn db_size(&self) -> u32 {
+        self.journal.header.initial_db_size
+    }
+
+    /// Number of pages whose original image is in the journal
+    pub(crate) fn page_count(&self) -> u32 {
+        self.pages.len() as u32
+    }
+
+    /// Original page image, `None` if the page wasn't changed by the transaction.
+    pub(crate) fn page_bytes(&self, page_no: u32) -> Result<Option<&[u8]>, SQLiteError> {
+        let offset = match self.pages.get(&page_no) {
+            Some(&offset) => offset,
+            None => return Ok(None),
+        };
+
+        let (_, page) =
+            journal_page(self.page_size())(&self.journal.buf.as_ref()[offset..]).finish()?;
+        Ok(Some(page.page_bytes))
+    }
+}
+
+pub struct JournalPages<'a> {
+    buf: &'a [u8],
+    /// Position of the next record or segment header
+    offset: usize,
+    /// Position of the last record returned
+    record_offset: usize,
+    /// Header of the current segment and the number of its records left
+    segment: Option<(JournalHeader, u32)>,
+    done: bool,
+}
+
+impl JournalPages<'_> {
+    /// Starts a segment at the current offset, if there is one
+    fn next_segment(&mut self) -> Option<(JournalHeader, u32)> {
+        let (_, header) = journal_header(self.buf.get(self.offset..)?).ok()?;
+        let sector_size = header.sector_size as usize;
+        let record_size = header.page_size as usize + 8;
+        if sector_size == 0 || header.page_size == 0 {
+            return None;
+        }
+
+        self.offset += sector_size;
+        let page_count = match header.page_count {
+            JOURNAL_PAGE_COUNT_UNKNOWN => {
+                (self.buf.len().saturating_sub(self.offset) / record_size) as u32
+            }
+            page_count => page_count,
+        };
+
+        Some((header, page_count))
+    }
+}
+
+impl<'a> Iterator for JournalPages<'a> {
+    type Item = Result<JournalPage<'a>, SQLiteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (header, left) = loop {
+            match self.segment {
+                Some((header, left)) if left > 0 => break (header, left),
+                Some((header, _)) => {
+                    // next segment header starts at a sector boundary
+                    let sector_size = header.sector_size as usize;
+                    self.offset = self.offset.div_ceil(sector_size) * sector_size;
+                }
+                None => {}
+            }
+
+            self.segment = self.next_segment();
+            if self.segment.is_none() {
+                self.done = true;
+                return None;
+            }
+        };
+
+        self.record_offset = self.offset;
+        let page = self
+            .buf
+            .get(self.offset..)
+            .and_then(|i| journal_page(header.page_size as usize)(i).ok());
+        match page {
+            Some((_, page)) if page.is_valid(header.nonce) => {
+                self.offset += header.page_size as usize + 8;
+                self.segment = Some((header, left - 1));
+                Some(Ok(page))
+            }
+            _ => {
+                self.done = true;
+                Some(Err(SQLiteError::InvalidJournalPageError(
+                    self.record_offset,
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_record_checksum() {
+        let mut page_bytes = vec![0u8; 1024];
+        // sampled bytes are at 824, 624, 424, 224 and 24, neither the first nor the last one
+        page_bytes[824] = 1;
+        page_bytes[224] = 2;
+        page_bytes[24] = 3;
+        page_bytes[0] = 100;
+        page_bytes[1023] = 100;
+
+        let page = JournalPage {
+            page_no: 1,
+            page_bytes: &page_bytes,
+            checksum: 13,
+        };
+        assert!(page.is_valid(7));
+        assert!(!page.is_valid(8));
+    }
+}
@@ -7,32 +7,64 @@
 extern crate core;
 
 use memmap2::{Mmap, MmapOptions};
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
+use std::ops::RangeBounds;
 use std::path::Path;
 
+use nom::combinator::map;
 use nom::Finish;
 
-use crate::error::{OwnedBytes, SQLiteError};
-use crate::model::{DbHeader, Page};
-use crate::parser::{db_header, page, root_page};
+use crate::carve::{carve_free_page, carve_page, CarvedRecord};
+use crate::chain::FreelistTrunks;
+use crate::compare::KeyColumn;
+use crate::cursor::{IndexCursor, TableCursor, MAX_DEPTH};
+use crate::error::SQLiteError;
+use crate::integrity::{IntegrityCheck, IntegrityProblem};
+use crate::journal::{is_hot_journal, JournalReader, JournalSnapshot};
+use crate::model::{
+    DbHeader, Freelist, IndexCellPayload, InteriorIndexCell, LeafIndexCell, LeafTableCell, Page,
+    PageLayout, Payload, PointerMapEntry, RawText, RowValues, TableCellPayload,
+};
+use crate::ownership::{PageMap, PageMapBuilder};
+use crate::parser::{
+    db_header, index_cell_payload, overflow_page, page_with_layout, pointer_map_page,
+    root_page_with_layout, table_cell_payload, HEADER_SIZE,
+};
+use crate::schema::{schema_entry, Schema, SchemaEntry};
+use crate::shm::WalIndex;
+use crate::wal::{WalCommit, WalReader, WalSnapshot};
 
 mod be_i48;
+pub mod carve;
+mod chain;
+pub mod compare;
+pub mod cursor;
+pub mod ddl;
 pub mod error;
+pub mod integrity;
+pub mod journal;
 pub mod model;
+pub mod ownership;
 pub mod parser;
+pub mod schema;
+pub mod shm;
 mod varint;
+pub mod wal;
 
 /*
-todo: parse additional page types (overflow, lock, freelist, ?)
-todo: determine when overflow page no is used
+todo: parse additional page types (lock, ?)
 todo: how page size computation works?
 todo: test with more records
-todo: how freelist pages work?
 */
 
 // todo: use bufreader
 pub struct Reader<S: AsRef<[u8]>> {
     buf: S,
+    /// Pages committed to the write-ahead log, which take precedence over the main file
+    wal: Option<WalSnapshot<S>>,
+    /// Original pages from a hot rollback journal, which take precedence over the main file
+    journal: Option<JournalSnapshot<S>>,
     pub header: DbHeader,
 }
 
@@ -66,6 +98,69 @@ impl Reader<Vec<u8>> {
         let buf: Vec<u8> = fs::read(&database)?;
         Reader::from_source(buf)
     }
+
+    /// Open a database in WAL mode together with its `-wal` file, both loaded into memory.
+    /// Pages are read as of the last transaction committed to the log, same as SQLite would.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let reader = sqlite_parser_nom::Reader::open_with_wal("app.db", "app.db-wal").unwrap();
+    /// ```
+    pub fn open_with_wal<P: AsRef<Path>, W: AsRef<Path>>(
+        database: P,
+        wal: W,
+    ) -> Result<Reader<Vec<u8>>, SQLiteError> {
+        use std::fs;
+
+        let buf: Vec<u8> = fs::read(&database)?;
+        let wal: Vec<u8> = fs::read(&wal)?;
+        Reader::from_source_with_wal(buf, wal)
+    }
+
+    /// Same as `open_with_wal`, but pages are looked up through the `-shm` wal-index
+    /// when it's consistent with the log, instead of replaying the whole log.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let reader =
+    ///     sqlite_parser_nom::Reader::open_with_wal_index("app.db", "app.db-wal", "app.db-shm")
+    ///         .unwrap();
+    /// ```
+    pub fn open_with_wal_index<P: AsRef<Path>, W: AsRef<Path>, I: AsRef<Path>>(
+        database: P,
+        wal: W,
+        wal_index: I,
+    ) -> Result<Reader<Vec<u8>>, SQLiteError> {
+        use std::fs;
+
+        let buf: Vec<u8> = fs::read(&database)?;
+        let wal: Vec<u8> = fs::read(&wal)?;
+        let wal_index: Vec<u8> = fs::read(&wal_index)?;
+        Reader::from_source_with_wal_index(buf, wal, wal_index)
+    }
+
+    /// Open a database together with its `-journal` file, both loaded into memory.
+    /// Pages are read as they were before the interrupted transaction, as if it was
+    /// rolled back, but neither file is changed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let reader =
+    ///     sqlite_parser_nom::Reader::open_with_journal("app.db", "app.db-journal").unwrap();
+    /// ```
+    pub fn open_with_journal<P: AsRef<Path>, J: AsRef<Path>>(
+        database: P,
+        journal: J,
+    ) -> Result<Reader<Vec<u8>>, SQLiteError> {
+        use std::fs;
+
+        let buf: Vec<u8> = fs::read(&database)?;
+        let journal: Vec<u8> = fs::read(&journal)?;
+        Reader::from_source_with_journal(buf, journal)
+    }
 }
 
 impl<S: AsRef<[u8]>> Reader<S> {
@@ -79,43 +174,647 @@ impl<S: AsRef<[u8]>> Reader<S> {
     /// let reader = sqlite_parser_nom::Reader::from_source(buf).unwrap();
     /// ```
     pub fn from_source(buf: S) -> Result<Reader<S>, SQLiteError> {
-        let (_, header) = db_header(buf.as_ref())
-            .finish()
-            .map_err(|e| nom::error::Error {
-                code: e.code,
-                input: OwnedBytes(e.input.to_owned()),
-            })?;
+        let (_, header) = db_header(buf.as_ref()).finish()?;
 
-        let reader = Reader { buf, header };
+        let reader = Reader {
+            buf,
+            wal: None,
+            journal: None,
+            header,
+        };
 
         Ok(reader)
     }
 
-    pub fn get_page(&self, pageno: u32) -> Result<Page, SQLiteError> {
-        let page_size = self.header.page_size.real_size();
-        let pageno = pageno as usize;
+    /// Open a SQLite database and its write-ahead log from anything that implements AsRef<[u8]>.
+    /// The main file could be empty if nothing was checkpointed yet.
+    pub fn from_source_with_wal(buf: S, wal: S) -> Result<Reader<S>, SQLiteError> {
+        let wal = WalSnapshot::new(WalReader::from_source(wal)?)?;
+        Reader::with_wal_snapshot(buf, wal)
+    }
 
-        let page_bytes = &self.buf.as_ref()[page_size * pageno..page_size * (pageno + 1)];
-        let page = if pageno == 0 {
-            root_page(page_bytes)
-        } else {
-            page(page_bytes)
+    /// Open a SQLite database, its write-ahead log and wal-index from anything that
+    /// implements AsRef<[u8]>. A wal-index which can't be read or is inconsistent is ignored.
+    pub fn from_source_with_wal_index(
+        buf: S,
+        wal: S,
+        wal_index: S,
+    ) -> Result<Reader<S>, SQLiteError> {
+        let wal = WalReader::from_source(wal)?;
+        let wal = match WalIndex::from_source(wal_index) {
+            Ok(wal_index) => WalSnapshot::with_index(wal, wal_index)?,
+            Err(_) => WalSnapshot::new(wal)?,
         };
+        Reader::with_wal_snapshot(buf, wal)
+    }
+
+    /// Open a SQLite database and its rollback journal from anything that implements AsRef<[u8]>.
+    /// A journal which isn't hot is ignored, the database file is already consistent.
+    pub fn from_source_with_journal(buf: S, journal: S) -> Result<Reader<S>, SQLiteError> {
+        if !is_hot_journal(journal.as_ref()) {
+            return Reader::from_source(buf);
+        }
+
+        let journal = JournalSnapshot::new(JournalReader::from_source(journal)?);
+        let (_, header) = match journal.page_bytes(1)? {
+            Some(first_page) => db_header(first_page),
+            None => db_header(buf.as_ref()),
+        }
+        .finish()?;
+        if header.page_size.real_size() != journal.page_size() {
+            return Err(SQLiteError::JournalPageSizeError(journal.page_size() as u32));
+        }
+
+        Ok(Reader {
+            buf,
+            wal: None,
+            journal: Some(journal),
+            header,
+        })
+    }
+
+    /// Number of pages in the database, counted the same way SQLite does. The size in the header
+    /// is trusted only if it's nonzero, `version_valid_for_no` matches `file_change_counter`
+    /// and the file is that long, otherwise pages are counted from the file length.
+    /// The size from the write-ahead log or the journal takes precedence, as long as
+    /// the pages missing from the file could be found there.
+    pub fn page_count(&self) -> u32 {
+        let file_pages = (self.buf.as_ref().len() / self.header.page_size.real_size()) as u32;
+
+        if let Some(db_size) = self.wal.as_ref().and_then(|wal| wal.db_size) {
+            let frame_count = self.wal.as_ref().map_or(0, |wal| wal.wal.frame_count());
+            return db_size.min(file_pages.saturating_add(frame_count));
+        }
+        if let Some(journal) = &self.journal {
+            return journal
+                .db_size()
+                .min(file_pages.saturating_add(journal.page_count()));
+        }
+
+        let header = &self.header;
+        if header.db_size != 0
+            && header.version_valid_for_no == header.file_change_counter
+            && header.db_size <= file_pages
+        {
+            header.db_size
+        } else {
+            file_pages
+        }
+    }
+
+    /// All pointer-map pages within the database.
+    pub fn ptrmap_page_nos(&self) -> Vec<u32> {
+        (2..=self.page_count())
+            .filter(|&page_no| self.header.is_ptrmap_page(page_no))
+            .collect()
+    }
+
+    /// Why rolling back stopped before the end of the journal, if a page record failed
+    /// verification. Records from there on are ignored, the same way SQLite does.
+    pub fn journal_error(&self) -> Option<&SQLiteError> {
+        self.journal.as_ref()?.error.as_ref()
+    }
+
+    /// Pages of the write-ahead log are looked up through the wal-index.
+    pub fn uses_wal_index(&self) -> bool {
+        self.wal.as_ref().is_some_and(|wal| wal.is_indexed())
+    }
+
+    fn with_wal_snapshot(buf: S, wal: WalSnapshot<S>) -> Result<Reader<S>, SQLiteError> {
+        let (_, header) = match wal.page_bytes(1)? {
+            Some(first_page) => db_header(first_page),
+            None => db_header(buf.as_ref()),
+        }
+        .finish()?;
+        if header.page_size.real_size() != wal.wal.page_size() {
+            return Err(SQLiteError::WalPageSizeError(wal.wal.header.page_size));
+        }
+
+        Ok(Reader {
+            buf,
+            wal: Some(wal),
+            journal: None,
+            header,
+        })
+    }
+
+    /// Transactions committed to the write-ahead log, oldest first.
+    /// Empty if the database was opened without one.
+    pub fn wal_commits(&self) -> &[WalCommit] {
+        self.wal.as_ref().map_or(&[], |wal| &wal.commits)
+    }
+
+    /// Database as it was right after the commit with the given index in `wal_commits`,
+    /// older page versions are still in the log until it's checkpointed.
+    pub fn at_commit(&self, commit: usize) -> Result<Reader<&[u8]>, SQLiteError> {
+        let wal = self
+            .wal
+            .as_ref()
+            .ok_or(SQLiteError::WalCommitOutOfBoundsError(commit))?;
+        let wal = WalSnapshot::at_commit(wal.wal.as_slice(), Some(commit))?;
+
+        Reader::with_wal_snapshot(self.buf.as_ref(), wal)
+    }
+
+    /// Why reading of the write-ahead log stopped before its end, if a frame failed verification.
+    /// Frames from there on are ignored, the same way SQLite recovery does.
+    pub fn wal_error(&self) -> Option<&SQLiteError> {
+        self.wal.as_ref()?.error.as_ref()
+    }
+
+    /// Parses the page and assembles payloads of cells which spill onto overflow pages,
+    /// so all their column values are available.
+    pub fn get_page(&self, pageno: u32) -> Result<Page<'_>, SQLiteError> {
+        let mut page = self.get_local_page(pageno)?;
+
+        match &mut page {
+            Page::InteriorIndex(p) => {
+                for cell in p.cells.iter_mut() {
+                    self.resolve_interior_index_cell(cell)?;
+                }
+            }
+            Page::LeafIndex(p) => {
+                for cell in p.cells.iter_mut() {
+                    self.resolve_leaf_index_cell(cell)?;
+                }
+            }
+            Page::InteriorTable(_) | Page::PointerMap(_) => {}
+            Page::LeafTable(p) => {
+                for cell in p.cells.iter_mut() {
+                    self.resolve_leaf_table_cell(cell)?;
+                }
+            }
+        }
 
-        let (_, page) = page.finish().map_err(|e| nom::error::Error {
-            code: e.code,
-            input: OwnedBytes(e.input.to_owned()),
-        })?;
+        Ok(page)
+    }
+
+    /// Parses the page as is, cells spilling onto overflow pages have only their local part decoded.
+    pub fn get_local_page(&self, pageno: u32) -> Result<Page<'_>, SQLiteError> {
+        let page_bytes = self.page_bytes(pageno)?;
+        let layout = PageLayout::from(&self.header);
+
+        let (_, page) = if pageno == 0 {
+            root_page_with_layout(layout)(page_bytes)
+        } else if self.header.is_ptrmap_page(pageno + 1) {
+            map(pointer_map_page, Page::PointerMap)(&page_bytes[..layout.usable_size])
+        } else {
+            page_with_layout(layout)(page_bytes)
+        }
+        .finish()?;
 
         Ok(page)
     }
+
+    /// Pointer-map entry for the given page, `page_no` is as stored in the database (1-based).
+    /// `None` if the database is not auto-vacuumed or there is no entry for this page.
+    pub fn ptrmap_entry(&self, page_no: u32) -> Result<Option<PointerMapEntry>, SQLiteError> {
+        let ptrmap_page_no = match self.header.ptrmap_page_no(page_no) {
+            Some(ptrmap_page_no) if ptrmap_page_no != page_no => ptrmap_page_no,
+            _ => return Ok(None),
+        };
+
+        let layout = PageLayout::from(&self.header);
+        let page_bytes = self.page_bytes(to_pageno(ptrmap_page_no)?)?;
+        let (_, ptrmap) = pointer_map_page(&page_bytes[..layout.usable_size]).finish()?;
+
+        let entry_no = (page_no - ptrmap_page_no - 1) as usize;
+        Ok(ptrmap.entries.get(entry_no).copied().flatten())
+    }
+
+    /// Follows the overflow chain and returns the complete payload of a cell.
+    /// `overflow_page_no` is the page number as stored in the cell.
+    /// Fails without reading the chain if the payload needs more pages than the database has,
+    /// and if the chain loops back on itself.
+    pub fn overflow_payload(
+        &self,
+        local_payload: &[u8],
+        overflow_page_no: u32,
+        payload_size: u64,
+    ) -> Result<Vec<u8>, SQLiteError> {
+        let layout = PageLayout::from(&self.header);
+        let overflow_size = usize::try_from(payload_size)
+            .unwrap_or(usize::MAX)
+            .saturating_sub(local_payload.len());
+        let overflow_page_count = overflow_size.div_ceil(layout.overflow_payload_size());
+        if overflow_page_count > self.page_count() as usize {
+            return Err(SQLiteError::PayloadSizeError(payload_size));
+        }
+
+        // bounded by the pages in the database, not the size stored in the cell
+        let payload_size = local_payload.len() + overflow_size;
+        let mut payload = Vec::with_capacity(payload_size);
+        payload.extend_from_slice(local_payload);
+
+        let mut visited = HashSet::new();
+        let mut next_page_no = Some(overflow_page_no);
+        for _ in 0..overflow_page_count {
+            let page_no = next_page_no
+                .filter(|&p| p != 0)
+                .ok_or(SQLiteError::TruncatedOverflowError(overflow_page_no))?;
+            if !visited.insert(page_no) {
+                return Err(SQLiteError::OverflowLoopError(page_no));
+            }
+            let page_bytes = self.page_bytes(to_pageno(page_no)?)?;
+            let (_, page) = overflow_page(&page_bytes[..layout.usable_size]).finish()?;
+
+            let take = page.payload.len().min(payload_size - payload.len());
+            payload.extend_from_slice(&page.payload[..take]);
+            next_page_no = page.next_page_no;
+        }
+
+        Ok(payload)
+    }
+
+    /// Decodes the text using the database text encoding, invalid sequences are replaced.
+    pub fn decode_text(&self, text: &RawText) -> String {
+        text.decode(self.header.db_text_encoding)
+    }
+
+    /// Decodes the text using the database text encoding, failing on invalid sequences.
+    pub fn decode_text_strict(&self, text: &RawText) -> Result<String, SQLiteError> {
+        text.decode_strict(self.header.db_text_encoding)
+    }
+
+    /// Walks the freelist trunk pages starting from the one in the header.
+    pub fn freelist(&self) -> Result<Freelist, SQLiteError> {
+        let mut freelist = Freelist::default();
+
+        for (page_no, trunk) in FreelistTrunks::new(self) {
+            if freelist.trunk_page_nos.contains(&page_no) {
+                return Err(SQLiteError::FreelistLoopError(page_no));
+            }
+
+            freelist.trunk_page_nos.push(page_no);
+            freelist.leaf_page_nos.extend(trunk?.leaf_page_nos);
+        }
+
+        Ok(freelist)
+    }
+
+    /// Numbers of all the pages on the freelist.
+    pub fn free_pages(&self) -> Result<BTreeSet<u32>, SQLiteError> {
+        Ok(self.freelist()?.pages())
+    }
+
+    /// Deleted records recovered from the freeblocks and the unallocated space of a table
+    /// b-tree page, `page_no` is as stored in the database (1-based).
+    /// Other kinds of pages have nothing to carve.
+    pub fn carve_page(&self, page_no: u32) -> Result<Vec<CarvedRecord<'_>>, SQLiteError> {
+        let page_bytes = self.page_bytes(to_pageno(page_no)?)?;
+        let usable_size = self.header.usable_size();
+        let header_offset = if page_no == 1 { HEADER_SIZE } else { 0 };
+
+        Ok(carve_page(
+            page_no,
+            &page_bytes[..usable_size],
+            header_offset,
+            self.header.db_text_encoding,
+        ))
+    }
+
+    /// Rows recovered from the freelist leaf pages which used to be table leaves, tagged with
+    /// the page number, together with the deleted records from their free space.
+    /// Trunk pages are skipped, the list of leaves overwrites their page header.
+    pub fn carve_freelist(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<CarvedRecord<'_>, SQLiteError>> + '_, SQLiteError> {
+        let layout = PageLayout::from(&self.header);
+        let freelist = self.freelist()?;
+
+        Ok(freelist.leaf_page_nos.into_iter().flat_map(move |page_no| {
+            let page_bytes = to_pageno(page_no).and_then(|pageno| self.page_bytes(pageno));
+            match page_bytes {
+                Ok(page_bytes) => carve_free_page(
+                    page_no,
+                    &page_bytes[..layout.usable_size],
+                    layout,
+                    self.header.db_text_encoding,
+                )
+                .into_iter()
+                .map(Ok)
+                .collect(),
+                Err(e) => vec![Err(e)],
+            }
+        }))
+    }
+
+    /// Walks every b-tree, overflow chain and the freelist, the same as
+    /// `PRAGMA integrity_check`, and lists the problems found. Empty if the database is sound.
+    /// Pointer-map entries of auto-vacuumed databases are checked against the walk too.
+    pub fn integrity_check(&self) -> Vec<IntegrityProblem> {
+        IntegrityCheck::new(self).run()
+    }
+
+    /// What every page of the database is used for: which table or index b-tree or overflow
+    /// chain it belongs to, the freelist, pointer-map, lock-byte, or nothing at all.
+    /// Fails only if the schema itself can't be read.
+    pub fn page_map(&self) -> Result<PageMap, SQLiteError> {
+        PageMapBuilder::new(self).build()
+    }
+
+    /// Deleted records recovered from all table b-tree pages, page by page.
+    /// Free pages are left to `carve_freelist`, pointer-map and lock-byte pages are skipped.
+    pub fn carve(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<CarvedRecord<'_>, SQLiteError>> + '_, SQLiteError> {
+        let free_pages = self.free_pages()?;
+        let lock_byte_page_no = self.header.lock_byte_page_no();
+
+        Ok((1..=self.page_count())
+            .filter(move |&page_no| {
+                !free_pages.contains(&page_no)
+                    && !self.header.is_ptrmap_page(page_no)
+                    && page_no != lock_byte_page_no
+            })
+            .flat_map(move |page_no| match self.carve_page(page_no) {
+                Ok(records) => records.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            }))
+    }
+
+    /// Rows of the table b-tree in rowid order, read lazily leaf by leaf.
+    /// `root_page_no` is as stored in the database (1-based), e.g. `rootpage` from sqlite_schema.
+    pub fn table_rows(&self, root_page_no: u32) -> TableCursor<'_, S> {
+        TableCursor::new(self, root_page_no, .., false)
+    }
+
+    /// Rows of the table b-tree from the highest rowid to the lowest.
+    pub fn table_rows_rev(&self, root_page_no: u32) -> TableCursor<'_, S> {
+        TableCursor::new(self, root_page_no, .., true)
+    }
+
+    /// Rows with rowids within the range, in rowid order.
+    /// Seeks to the start of the range, without reading the rows before it.
+    pub fn table_range<R: RangeBounds<i64>>(
+        &self,
+        root_page_no: u32,
+        range: R,
+    ) -> TableCursor<'_, S> {
+        TableCursor::new(self, root_page_no, range, false)
+    }
+
+    /// Rows with rowids within the range, from the highest rowid to the lowest.
+    /// Seeks to the end of the range, without reading the rows after it.
+    pub fn table_range_rev<R: RangeBounds<i64>>(
+        &self,
+        root_page_no: u32,
+        range: R,
+    ) -> TableCursor<'_, S> {
+        TableCursor::new(self, root_page_no, range, true)
+    }
+
+    /// Looks up a single row by rowid, only reading the pages on the path from the root.
+    /// `root_page_no` is as stored in the database (1-based), e.g. `rootpage` from sqlite_schema.
+    pub fn get_row(
+        &self,
+        root_page_no: u32,
+        rowid: i64,
+    ) -> Result<Option<TableCellPayload<'_>>, SQLiteError> {
+        let mut page_no = root_page_no;
+        for _ in 0..=MAX_DEPTH {
+            match self.get_local_page(to_pageno(page_no)?)? {
+                Page::InteriorTable(p) => {
+                    // left child holds rowids up to and including the key
+                    let child = p.cells.partition_point(|c| c.integer_key < rowid);
+                    page_no = p
+                        .cells
+                        .get(child)
+                        .map_or(p.header.rightmost_pointer, |c| c.left_child_page_no);
+                }
+                Page::LeafTable(mut p) => {
+                    return match p.cells.binary_search_by_key(&rowid, |c| c.rowid) {
+                        Ok(found) => {
+                            let mut cell = p.cells.swap_remove(found);
+                            self.resolve_leaf_table_cell(&mut cell)?;
+                            Ok(Some(cell.payload))
+                        }
+                        Err(_) => Ok(None),
+                    };
+                }
+                _ => return Err(SQLiteError::UnexpectedPageTypeError(page_no)),
+            }
+        }
+
+        Err(SQLiteError::BTreeTooDeepError(page_no))
+    }
+
+    /// Entries of the index b-tree in the index sort order, read lazily page by page.
+    /// `root_page_no` is as stored in the database (1-based), e.g. `rootpage` from sqlite_schema.
+    pub fn index_entries(&self, root_page_no: u32) -> IndexCursor<'_, S> {
+        IndexCursor::new(self, root_page_no)
+    }
+
+    /// Rows of a rowid table in rowid order, with values as `SELECT *` returns them.
+    /// See `TableDefinition::row_values` for how the record is completed.
+    pub fn decoded_rows(
+        &self,
+        table: &SchemaEntry,
+    ) -> Result<impl Iterator<Item = Result<(i64, RowValues<'_>), SQLiteError>> + '_, SQLiteError>
+    {
+        let definition = table.table_definition()?;
+        let root_page_no = match table.root_page {
+            Some(root_page_no) if !definition.without_rowid => root_page_no,
+            _ => {
+                return Err(SQLiteError::SchemaError(format!(
+                    "`{}` is not a rowid table",
+                    table.name
+                )))
+            }
+        };
+
+        Ok(self.table_rows(root_page_no).map(move |row| {
+            let (rowid, payload) = row?;
+            Ok((
+                rowid,
+                definition.row_values(Some(rowid), payload.column_values)?,
+            ))
+        }))
+    }
+
+    /// Rows of a WITHOUT ROWID table in primary key order, with values in declared column order.
+    /// Such tables are stored as index b-trees keyed by the primary key columns.
+    pub fn without_rowid_rows(
+        &self,
+        table: &SchemaEntry,
+    ) -> Result<impl Iterator<Item = Result<RowValues<'_>, SQLiteError>> + '_, SQLiteError> {
+        let definition = table.table_definition()?;
+        let root_page_no = match table.root_page {
+            Some(root_page_no) if definition.without_rowid => root_page_no,
+            _ => {
+                return Err(SQLiteError::SchemaError(format!(
+                    "`{}` is not a WITHOUT ROWID table",
+                    table.name
+                )))
+            }
+        };
+
+        Ok(self
+            .index_entries(root_page_no)
+            .map(move |entry| definition.row_values(None, entry?.column_values)))
+    }
+
+    /// Rowids of the index entries starting with the key prefix, in the index order.
+    /// Collations and sort orders are taken from the schema, including those of automatic
+    /// indexes and WITHOUT ROWID tables, use `index_seek_with` to override.
+    pub fn index_seek(
+        &self,
+        index_root_page_no: u32,
+        key_prefix: &[Option<Payload>],
+    ) -> Result<Vec<i64>, SQLiteError> {
+        let columns = self.key_columns(index_root_page_no)?;
+        self.index_seek_with(index_root_page_no, key_prefix, &columns)
+    }
+
+    /// Same as `index_seek`, with explicit collations and sort orders of the index columns.
+    pub fn index_seek_with(
+        &self,
+        index_root_page_no: u32,
+        key_prefix: &[Option<Payload>],
+        columns: &[KeyColumn],
+    ) -> Result<Vec<i64>, SQLiteError> {
+        let key = owned_values(key_prefix.to_vec());
+
+        IndexCursor::seek(self, index_root_page_no, key, columns.to_vec())
+            .filter_map(|entry| entry.map(|e| e.rowid()).transpose())
+            .collect()
+    }
+
+    /// Collations and sort orders of the b-tree record columns, see `Schema::key_columns`.
+    /// Resolve them once for repeated `index_seek_with` calls, as it reads the whole schema.
+    pub fn key_columns(&self, root_page_no: u32) -> Result<Vec<KeyColumn>, SQLiteError> {
+        self.schema()?.key_columns(root_page_no)
+    }
+
+    /// Reads the whole sqlite_schema table, which is rooted at the first page.
+    pub fn schema(&self) -> Result<Schema, SQLiteError> {
+        let entries = self
+            .table_rows(1)
+            .map(|row| schema_entry(&row?.1, self.header.db_text_encoding))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Schema { entries })
+    }
+
+    pub(crate) fn resolve_leaf_table_cell(
+        &self,
+        cell: &mut LeafTableCell<'_>,
+    ) -> Result<(), SQLiteError> {
+        if let Some(overflow_page_no) = cell.overflow_page_no {
+            let payload =
+                self.overflow_payload(cell.local_payload, overflow_page_no, cell.payload_size)?;
+            cell.payload = owned_table_payload(&payload)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn resolve_leaf_index_cell(
+        &self,
+        cell: &mut LeafIndexCell<'_>,
+    ) -> Result<(), SQLiteError> {
+        if let Some(overflow_page_no) = cell.overflow_page_no {
+            let payload =
+                self.overflow_payload(cell.local_payload, overflow_page_no, cell.payload_size)?;
+            cell.payload = owned_index_payload(&payload)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn resolve_interior_index_cell(
+        &self,
+        cell: &mut InteriorIndexCell<'_>,
+    ) -> Result<(), SQLiteError> {
+        if let Some(overflow_page_no) = cell.overflow_page_no {
+            let payload =
+                self.overflow_payload(cell.local_payload, overflow_page_no, cell.payload_size)?;
+            cell.payload = owned_index_payload(&payload)?;
+        }
+
+        Ok(())
+    }
+
+    fn page_bytes(&self, pageno: u32) -> Result<&[u8], SQLiteError> {
+        if let Some(wal) = &self.wal {
+            // the last commit could have truncated the database
+            if wal.db_size.is_some_and(|db_size| pageno >= db_size) {
+                return Err(SQLiteError::PageOutOfBoundsError(pageno + 1));
+            }
+            if let Some(page_bytes) = wal.page_bytes(pageno + 1)? {
+                return Ok(page_bytes);
+            }
+        }
+        if let Some(journal) = &self.journal {
+            // pages appended by the transaction didn't exist before it
+            if pageno >= journal.db_size() {
+                return Err(SQLiteError::PageOutOfBoundsError(pageno + 1));
+            }
+            if let Some(page_bytes) = journal.page_bytes(pageno + 1)? {
+                return Ok(page_bytes);
+            }
+        }
+
+        let page_size = self.header.page_size.real_size();
+        let start = page_size * pageno as usize;
+
+        self.buf
+            .as_ref()
+            .get(start..start + page_size)
+            .ok_or(SQLiteError::PageOutOfBoundsError(pageno + 1))
+    }
+}
+
+/// Converts page number as stored in the database (1-based) into `pageno` of `Reader::get_page`.
+pub(crate) fn to_pageno(page_no: u32) -> Result<u32, SQLiteError> {
+    page_no
+        .checked_sub(1)
+        .ok_or(SQLiteError::PageOutOfBoundsError(page_no))
+}
+
+fn owned_table_payload(payload: &[u8]) -> Result<TableCellPayload<'static>, SQLiteError> {
+    let (_, payload) = table_cell_payload(payload).finish()?;
+
+    Ok(TableCellPayload {
+        header_size: payload.header_size,
+        column_types: payload.column_types,
+        column_values: owned_values(payload.column_values),
+    })
+}
+
+fn owned_index_payload(payload: &[u8]) -> Result<IndexCellPayload<'static>, SQLiteError> {
+    let (_, payload) = index_cell_payload(payload).finish()?;
+
+    Ok(IndexCellPayload {
+        header_size: payload.header_size,
+        column_types: payload.column_types,
+        column_values: owned_values(payload.column_values),
+    })
+}
+
+fn owned_values(values: Vec<Option<Payload>>) -> Vec<Option<Payload<'static>>> {
+    values
+        .into_iter()
+        .map(|v| v.map(Payload::into_owned))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::model::Page;
-    use crate::model::SerialType::{Null, Text, I8};
+    use crate::carve::{Confidence, FreeSpace};
+    use crate::compare::Collation;
+    use crate::ddl::{DefaultValue, SortOrder};
+    use crate::model::SerialType::{Blob, Null, Text, I8};
+    use crate::model::{Page, TextEncoding};
+    use crate::ownership::{BTreePageKind, PageOwner};
+    use crate::schema::SchemaEntryKind;
+    use crate::shm::{HASH_TABLE_PAGES, HASH_TABLE_PAGES_FIRST, WAL_INDEX_HEADER_SIZE};
+    use crate::wal::{
+        wal_checksum, WalFrame, WAL_FRAME_HEADER_SIZE, WAL_HEADER_SIZE, WAL_MAGIC_BE,
+    };
     use rusqlite::Connection;
+    use std::collections::HashMap;
+    use std::ops::Bound;
     use tempfile::tempdir;
 
     use super::*;
@@ -204,4 +903,1900 @@ mod tests {
             _ => unreachable!("root page should be table leaf page"),
         }
     }
+
+    #[test]
+    fn parse_overflowing_payloads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("overflow.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT, bar BLOB)",
+            (),
+        )
+        .unwrap();
+        conn.execute("CREATE INDEX test_foo ON test (foo)", ())
+            .unwrap();
+        let long_text = "tjena ".repeat(1000);
+        let long_blob = vec![0xabu8; 10_000];
+        conn.execute(
+            "INSERT INTO test VALUES (1, ?1, ?2)",
+            (&long_text, &long_blob),
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        match reader.get_page(1).unwrap() {
+            Page::LeafTable(p) => {
+                let cell = p.cells.first().unwrap();
+                assert!(cell.overflow_page_no.is_some());
+                assert_eq!(
+                    cell.payload.column_values,
+                    vec![
+                        None,
+                        Some(long_text.as_str().into()),
+                        Some(long_blob.as_slice().into()),
+                    ]
+                );
+            }
+            _ => unreachable!("table root page should be table leaf page"),
+        }
+
+        match reader.get_local_page(1).unwrap() {
+            Page::LeafTable(p) => {
+                let cell = p.cells.first().unwrap();
+                assert_eq!(cell.payload.column_types.len(), 3);
+                assert_eq!(cell.payload.column_values, vec![None]);
+            }
+            _ => unreachable!("table root page should be table leaf page"),
+        }
+
+        match reader.get_page(2).unwrap() {
+            Page::LeafIndex(p) => {
+                let cell = p.cells.first().unwrap();
+                assert!(cell.overflow_page_no.is_some());
+                assert_eq!(
+                    cell.payload.column_values,
+                    vec![Some(long_text.as_str().into()), Some(1i8.into())]
+                );
+                assert_eq!(cell.payload.rowid(), Some(1));
+            }
+            _ => unreachable!("index root page should be index leaf page"),
+        }
+    }
+
+    #[test]
+    fn reject_corrupted_overflow_chains() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("overflow-corrupted.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo BLOB);
+            INSERT INTO test VALUES (1, zeroblob(12000));",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let reader = Reader::from_source(buf.clone()).unwrap();
+        let overflow_page_no = match reader.get_local_page(1).unwrap() {
+            Page::LeafTable(p) => p.cells[0].overflow_page_no.unwrap(),
+            _ => unreachable!("table root page should be table leaf page"),
+        };
+        let cell_start = 4096 + u16::from_be_bytes([buf[4096 + 8], buf[4096 + 9]]) as usize;
+
+        // payload size of 2^57 bytes, more than the whole database could hold
+        let mut corrupted = buf.clone();
+        corrupted[cell_start..][..9]
+            .copy_from_slice(&[0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]);
+        let reader = Reader::from_source(corrupted).unwrap();
+        assert!(matches!(
+            reader.get_page(1),
+            Err(SQLiteError::PayloadSizeError(size)) if size == 1 << 57
+        ));
+        assert!(!reader.integrity_check().is_empty());
+        assert!(reader.page_map().is_ok());
+
+        // the first overflow page points back to itself
+        let mut corrupted = buf;
+        let overflow_start = (overflow_page_no as usize - 1) * 4096;
+        corrupted[overflow_start..][..4].copy_from_slice(&overflow_page_no.to_be_bytes());
+        let reader = Reader::from_source(corrupted).unwrap();
+        assert!(matches!(
+            reader.get_page(1),
+            Err(SQLiteError::OverflowLoopError(page_no)) if page_no == overflow_page_no
+        ));
+    }
+
+    #[test]
+    fn walk_freelist() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("freelist.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo BLOB)", ())
+            .unwrap();
+        for id in 0..100 {
+            conn.execute("INSERT INTO test VALUES (?1, zeroblob(2000))", [id])
+                .unwrap();
+        }
+        conn.execute("DELETE FROM test", ()).unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let freelist = reader.freelist().unwrap();
+
+        assert_eq!(
+            freelist.trunk_page_nos,
+            vec![reader.header.first_freelist_page_no]
+        );
+        assert_eq!(
+            reader.free_pages().unwrap().len(),
+            reader.header.total_freelist_pages as usize
+        );
+        assert!(reader.header.total_freelist_pages > 40);
+        assert!(!reader.free_pages().unwrap().contains(&2));
+    }
+
+    #[test]
+    fn parse_pointer_map() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ptrmap.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = FULL;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo BLOB);
+            INSERT INTO test VALUES (1, zeroblob(10000));",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        assert!(reader.header.is_auto_vacuum());
+        assert!(!reader.header.is_incremental_vacuum());
+        assert_eq!(reader.ptrmap_page_nos(), vec![2]);
+
+        match reader.get_page(1).unwrap() {
+            Page::PointerMap(p) => {
+                assert_eq!(p.entries.len(), 4096 / 5);
+                let used = p.entries.iter().take_while(|e| e.is_some()).count();
+                assert_eq!(used, reader.header.db_size as usize - 2);
+                assert!(p.entries[used..].iter().all(Option::is_none));
+            }
+            _ => unreachable!("second page should be pointer-map page"),
+        }
+
+        assert_eq!(reader.ptrmap_entry(1).unwrap(), None);
+        assert_eq!(reader.ptrmap_entry(2).unwrap(), None);
+        assert_eq!(
+            reader.ptrmap_entry(3).unwrap(),
+            Some(PointerMapEntry::RootPage)
+        );
+
+        let first_overflow_page_no = match reader.get_page(2).unwrap() {
+            Page::LeafTable(p) => p.cells.first().unwrap().overflow_page_no.unwrap(),
+            _ => unreachable!("table root page should be table leaf page"),
+        };
+        assert_eq!(
+            reader.ptrmap_entry(first_overflow_page_no).unwrap(),
+            Some(PointerMapEntry::Overflow1 { parent_page_no: 3 })
+        );
+        assert_eq!(
+            reader.ptrmap_entry(first_overflow_page_no + 1).unwrap(),
+            Some(PointerMapEntry::Overflow2 {
+                parent_page_no: first_overflow_page_no
+            })
+        );
+    }
+
+    #[test]
+    fn distinguish_empty_values_from_null() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("empty.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (a TEXT, b BLOB, c);
+            INSERT INTO test VALUES ('', x'', NULL);",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let row = reader.get_row(2, 1).unwrap().unwrap();
+        assert_eq!(row.column_types, vec![Text(13), Blob(12), Null]);
+        assert_eq!(
+            row.column_values,
+            vec![Some("".into()), Some((&[][..]).into()), None]
+        );
+    }
+
+    #[test]
+    fn keep_pointer_map_entries_after_empty_slot() {
+        // the lock-byte page slot stays empty in the middle of a pointer-map page
+        let mut page = vec![0u8; 4096];
+        page[..5].copy_from_slice(&[1, 0, 0, 0, 0]);
+        page[10..15].copy_from_slice(&[5, 0, 0, 0, 3]);
+
+        let (_, ptrmap) = pointer_map_page(&page).finish().unwrap();
+        assert_eq!(ptrmap.entries.len(), 4096 / 5);
+        assert_eq!(
+            ptrmap.entries[..3],
+            [
+                Some(PointerMapEntry::RootPage),
+                None,
+                Some(PointerMapEntry::BTree { parent_page_no: 3 })
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_with_reserved_bytes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("reserved.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        let mut reserved_bytes: std::os::raw::c_int = 32;
+        let rc = unsafe {
+            rusqlite::ffi::sqlite3_file_control(
+                conn.handle(),
+                b"main\0".as_ptr().cast(),
+                rusqlite::ffi::SQLITE_FCNTL_RESERVE_BYTES,
+                &mut reserved_bytes as *mut _ as *mut _,
+            )
+        };
+        assert_eq!(rc, rusqlite::ffi::SQLITE_OK);
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT)", ())
+            .unwrap();
+        // fits into the page without reserved space, but not into the usable area
+        let text = "a".repeat(4096 - 35 - 10);
+        conn.execute("INSERT INTO test VALUES (1, ?1)", [&text])
+            .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        assert_eq!(reader.header.reserved_bytes, 32);
+        assert_eq!(reader.header.usable_size(), 4096 - 32);
+
+        match reader.get_page(1).unwrap() {
+            Page::LeafTable(p) => {
+                assert_eq!(p.reserved.len(), 32);
+                let cell = p.cells.first().unwrap();
+                assert!(cell.overflow_page_no.is_some());
+                assert_eq!(
+                    cell.payload.column_values,
+                    vec![None, Some(text.as_str().into())]
+                );
+            }
+            _ => unreachable!("table root page should be table leaf page"),
+        }
+    }
+
+    #[test]
+    fn decode_utf16_text() {
+        for (encoding, text_encoding) in [
+            ("UTF-16le", TextEncoding::Utf16Le),
+            ("UTF-16be", TextEncoding::Utf16Be),
+        ] {
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("utf16.sqlite3");
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(&format!("PRAGMA encoding = '{}'", encoding))
+                .unwrap();
+            conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT)", ())
+                .unwrap();
+            conn.execute("INSERT INTO test VALUES (1, 'tjena 🦀 hallå')", ())
+                .unwrap();
+            conn.close().unwrap();
+
+            let reader = Reader::open_readfile(&path).unwrap();
+            assert_eq!(reader.header.db_text_encoding, text_encoding);
+
+            match reader.get_page(1).unwrap() {
+                Page::LeafTable(p) => match &p.cells.first().unwrap().payload.column_values[1] {
+                    Some(Payload::Text(text)) => {
+                        assert_eq!(reader.decode_text(text), "tjena 🦀 hallå");
+                        assert_eq!(reader.decode_text_strict(text).unwrap(), "tjena 🦀 hallå");
+                    }
+                    _ => unreachable!("second column should be text"),
+                },
+                _ => unreachable!("table root page should be table leaf page"),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_invalid_utf16_text() {
+        // lone high surrogate followed by "a"
+        let text = RawText::new(&[0x3d, 0xd8, 0x61, 0x00]);
+
+        assert_eq!(text.decode(TextEncoding::Utf16Le), "\u{FFFD}a");
+        assert!(matches!(
+            text.decode_strict(TextEncoding::Utf16Le),
+            Err(SQLiteError::TextDecodingError(TextEncoding::Utf16Le))
+        ));
+        assert!(RawText::new(&[0x00, 0x61, 0x00])
+            .decode_strict(TextEncoding::Utf16Be)
+            .is_err());
+    }
+
+    #[test]
+    fn parse_rowid_varint_boundaries() {
+        let mut rowids = vec![i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX];
+        for bits in (7..=56).step_by(7) {
+            rowids.push((1i64 << bits) - 1);
+            rowids.push(1i64 << bits);
+        }
+        rowids.sort();
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rowids.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo INTEGER)",
+            (),
+        )
+        .unwrap();
+        conn.execute("CREATE INDEX test_foo ON test (foo)", ())
+            .unwrap();
+        for &rowid in rowids.iter() {
+            conn.execute("INSERT INTO test VALUES (?1, 0)", [rowid])
+                .unwrap();
+        }
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        match reader.get_page(1).unwrap() {
+            Page::LeafTable(p) => {
+                let parsed: Vec<i64> = p.cells.iter().map(|c| c.rowid).collect();
+                assert_eq!(parsed, rowids);
+            }
+            _ => unreachable!("table root page should be table leaf page"),
+        }
+
+        match reader.get_page(2).unwrap() {
+            Page::LeafIndex(p) => {
+                let parsed: Vec<i64> = p.cells.iter().filter_map(|c| c.payload.rowid()).collect();
+                assert_eq!(parsed, rowids);
+            }
+            _ => unreachable!("index root page should be index leaf page"),
+        }
+    }
+
+    #[test]
+    fn parse_wal_frames() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("wal.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        let mut reserved_bytes: std::os::raw::c_int = 16;
+        let rc = unsafe {
+            rusqlite::ffi::sqlite3_file_control(
+                conn.handle(),
+                b"main\0".as_ptr().cast(),
+                rusqlite::ffi::SQLITE_FCNTL_RESERVE_BYTES,
+                &mut reserved_bytes as *mut _ as *mut _,
+            )
+        };
+        assert_eq!(rc, rusqlite::ffi::SQLITE_OK);
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            INSERT INTO test VALUES (1, 'one');
+            INSERT INTO test VALUES (2, 'two');",
+        )
+        .unwrap();
+        // the log is checkpointed and removed once the last connection closes
+        let wal = std::fs::read(dir.path().join("wal.sqlite3-wal")).unwrap();
+        conn.close().unwrap();
+
+        let reader = WalReader::from_source(wal).unwrap();
+        assert_eq!(reader.page_size(), 4096);
+        assert_eq!(reader.header.file_format, 3007000);
+
+        let frames: Vec<WalFrame> = reader.frames().map(Result::unwrap).collect();
+        assert_eq!(frames.len() as u32, reader.frame_count());
+        // schema change, then one transaction per insert
+        assert_eq!(frames.iter().filter(|f| f.header.is_commit()).count(), 3);
+        assert!(frames.last().unwrap().header.is_commit());
+        assert!(frames
+            .iter()
+            .all(|f| f.header.salt_1 == reader.header.salt_1));
+
+        let first = frames.iter().find(|f| f.header.page_no == 1).unwrap();
+        let (_, db_header) = db_header(first.page_bytes).finish().unwrap();
+        let layout = PageLayout::from(&db_header);
+        assert_eq!(layout.usable_size, 4096 - 16);
+
+        let last = frames.iter().rfind(|f| f.header.page_no == 2).unwrap();
+        match last.page(layout).unwrap() {
+            Page::LeafTable(p) => {
+                let rowids: Vec<i64> = p.cells.iter().map(|c| c.rowid).collect();
+                assert_eq!(rowids, vec![1, 2]);
+                assert_eq!(p.reserved.len(), 16);
+            }
+            _ => unreachable!("table root should be a leaf page"),
+        }
+        assert!(matches!(first.page(layout), Ok(Page::LeafTable(_))));
+
+        assert!(reader.frame(0).unwrap().is_none());
+        assert!(reader.frame(reader.frame_count() + 1).unwrap().is_none());
+        assert!(WalReader::from_source(vec![0u8; 32]).is_err());
+    }
+
+    #[test]
+    fn overlay_wal_on_main_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("overlay.sqlite3");
+        let wal_path = dir.path().join("overlay.sqlite3-wal");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            BEGIN;",
+        )
+        .unwrap();
+        for id in 0..500 {
+            conn.execute(
+                "INSERT INTO test VALUES (?1, ?2)",
+                (id, format!("old {id}")),
+            )
+            .unwrap();
+        }
+        conn.execute_batch(
+            "COMMIT;
+            PRAGMA wal_checkpoint(TRUNCATE);
+            UPDATE test SET foo = 'new ' || id WHERE id % 3 = 0;
+            DELETE FROM test WHERE id % 5 = 0;
+            CREATE TABLE other (bar);
+            INSERT INTO other VALUES ('only in the log');",
+        )
+        .unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT id, foo FROM test ORDER BY id")
+            .unwrap();
+        let expected: Vec<(i64, String)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        drop(stmt);
+
+        // copies taken while the log is still there
+        let main = std::fs::read(&path).unwrap();
+        let mut wal = std::fs::read(&wal_path).unwrap();
+        conn.close().unwrap();
+
+        let rows = |reader: &Reader<Vec<u8>>| -> Vec<(i64, String)> {
+            let schema = reader.schema().unwrap();
+            reader
+                .decoded_rows(schema.table("test").unwrap())
+                .unwrap()
+                .map(|row| {
+                    let (rowid, values) = row.unwrap();
+                    match &values[1] {
+                        Some(Payload::Text(foo)) => (rowid, reader.decode_text(foo)),
+                        _ => unreachable!("foo should be text"),
+                    }
+                })
+                .collect()
+        };
+
+        let stale = Reader::from_source(main.clone()).unwrap();
+        assert_eq!(rows(&stale).len(), 500);
+        assert!(stale.schema().unwrap().table("other").is_none());
+
+        let reader = Reader::from_source_with_wal(main.clone(), wal.clone()).unwrap();
+        assert_eq!(rows(&reader), expected);
+        assert!(reader.schema().unwrap().table("other").is_some());
+        assert_ne!(reader.header.schema_cookie, stale.header.schema_cookie);
+
+        // frames of an unfinished transaction are not visible
+        let wal_reader = WalReader::from_source(wal.clone()).unwrap();
+        let frame_size = WAL_FRAME_HEADER_SIZE + wal_reader.page_size();
+        let first_frame = wal[WAL_HEADER_SIZE..WAL_HEADER_SIZE + frame_size].to_vec();
+        assert!(!wal_reader.frame(1).unwrap().unwrap().header.is_commit());
+        drop(wal_reader);
+        wal.extend_from_slice(&first_frame);
+
+        let reader = Reader::from_source_with_wal(main, wal).unwrap();
+        assert_eq!(rows(&reader), expected);
+
+        let from_files = Reader::open_with_wal(&path, dir.path().join("missing-wal"));
+        assert!(from_files.is_err());
+    }
+
+    #[test]
+    fn verify_wal_checksums() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checksums.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            PRAGMA wal_checkpoint(TRUNCATE);
+            INSERT INTO test VALUES (1, 'first');
+            INSERT INTO test VALUES (2, 'second');
+            INSERT INTO test VALUES (3, 'third');",
+        )
+        .unwrap();
+        let main = std::fs::read(&path).unwrap();
+        let wal = std::fs::read(dir.path().join("checksums.sqlite3-wal")).unwrap();
+        conn.close().unwrap();
+
+        let rowids = |wal: Vec<u8>| {
+            let reader = Reader::from_source_with_wal(main.clone(), wal).unwrap();
+            let rowids: Vec<i64> = reader.table_rows(2).map(|r| r.unwrap().0).collect();
+            (rowids, reader.wal_error().map(|e| e.to_string()))
+        };
+        assert_eq!(rowids(wal.clone()), (vec![1, 2, 3], None));
+
+        // one frame per transaction, the table fits on its root page
+        let frame_size = WAL_FRAME_HEADER_SIZE + 4096;
+        let frame_start = |frame_no: usize| WAL_HEADER_SIZE + (frame_no - 1) * frame_size;
+        assert_eq!(wal.len(), frame_start(4));
+
+        let mut corrupted = wal.clone();
+        corrupted[frame_start(2) + WAL_FRAME_HEADER_SIZE + 100] ^= 1;
+        assert_eq!(
+            rowids(corrupted),
+            (
+                vec![1],
+                Some(SQLiteError::InvalidWalFrameError(2).to_string())
+            )
+        );
+
+        let mut corrupted = wal.clone();
+        corrupted[WAL_HEADER_SIZE - 1] ^= 1;
+        assert_eq!(
+            rowids(corrupted),
+            (
+                vec![],
+                Some(SQLiteError::WalHeaderChecksumError.to_string())
+            )
+        );
+
+        // same log with big-endian checksums, as written on big-endian machines
+        let mut big_endian = wal.clone();
+        big_endian[..4].copy_from_slice(&WAL_MAGIC_BE.to_be_bytes());
+        let mut checksum = wal_checksum(&big_endian[..WAL_HEADER_SIZE - 8], true, (0, 0));
+        big_endian[24..28].copy_from_slice(&checksum.0.to_be_bytes());
+        big_endian[28..32].copy_from_slice(&checksum.1.to_be_bytes());
+        for frame_no in 1..=3 {
+            let start = frame_start(frame_no);
+            checksum = wal_checksum(&big_endian[start..start + 8], true, checksum);
+            checksum = wal_checksum(
+                &big_endian[start + WAL_FRAME_HEADER_SIZE..start + frame_size],
+                true,
+                checksum,
+            );
+            big_endian[start + 16..start + 20].copy_from_slice(&checksum.0.to_be_bytes());
+            big_endian[start + 20..start + 24].copy_from_slice(&checksum.1.to_be_bytes());
+        }
+        assert_ne!(big_endian, wal);
+        assert_eq!(rowids(big_endian), (vec![1, 2, 3], None));
+    }
+
+    #[test]
+    fn open_wal_commits() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("history.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            INSERT INTO test VALUES (1, 'first');
+            UPDATE test SET foo = 'second' WHERE id = 1;
+            BEGIN;
+            UPDATE test SET foo = 'third' WHERE id = 1;
+            INSERT INTO test VALUES (2, 'new');
+            COMMIT;
+            DELETE FROM test WHERE id = 1;",
+        )
+        .unwrap();
+        let main = std::fs::read(&path).unwrap();
+        let wal = std::fs::read(dir.path().join("history.sqlite3-wal")).unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::from_source_with_wal(main, wal.clone()).unwrap();
+        let commits = reader.wal_commits();
+        assert_eq!(commits.len(), 5);
+        assert!(commits.windows(2).all(|c| c[0].frame_no < c[1].frame_no));
+        assert_eq!(commits.last().unwrap().db_size, 2);
+
+        let foo = |reader: &Reader<&[u8]>, rowid| match reader.get_row(2, rowid).unwrap() {
+            Some(row) => match &row.column_values[1] {
+                Some(Payload::Text(foo)) => Some(reader.decode_text(foo)),
+                _ => unreachable!("foo should be text"),
+            },
+            None => None,
+        };
+
+        let history: Vec<(Option<String>, Option<String>)> = (1..5)
+            .map(|commit| {
+                let snapshot = reader.at_commit(commit).unwrap();
+                (foo(&snapshot, 1), foo(&snapshot, 2))
+            })
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (Some("first".into()), None),
+                (Some("second".into()), None),
+                (Some("third".into()), Some("new".into())),
+                (None, Some("new".into())),
+            ]
+        );
+
+        let before_table = reader.at_commit(0).unwrap();
+        assert_eq!(before_table.schema().unwrap().tables().count(), 1);
+        assert_eq!(before_table.wal_commits(), commits);
+
+        assert!(matches!(
+            reader.at_commit(5),
+            Err(SQLiteError::WalCommitOutOfBoundsError(5))
+        ));
+
+        // a database without the log has no commits to go back to
+        let wal_reader = WalReader::from_source(wal).unwrap();
+        let first_page = wal_reader.frame(1).unwrap().unwrap().page_bytes;
+        let without_wal = Reader::from_source(first_page).unwrap();
+        assert!(without_wal.wal_commits().is_empty());
+        assert!(without_wal.at_commit(0).is_err());
+    }
+
+    #[test]
+    fn lookup_wal_frames_through_wal_index() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shm.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA page_size = 512;
+            PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            PRAGMA synchronous = OFF;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            CREATE INDEX test_foo ON test (foo);",
+        )
+        .unwrap();
+        // enough frames to fill more than the first hash table block
+        for id in 0..2000 {
+            conn.execute(
+                "INSERT INTO test VALUES (?1, ?2)",
+                (id, format!("{id}").repeat(20)),
+            )
+            .unwrap();
+        }
+
+        let main = std::fs::read(&path).unwrap();
+        let wal = std::fs::read(dir.path().join("shm.sqlite3-wal")).unwrap();
+        let shm = std::fs::read(dir.path().join("shm.sqlite3-shm")).unwrap();
+        conn.close().unwrap();
+
+        let index = WalIndex::from_source(shm.as_slice()).unwrap();
+        let wal_reader = WalReader::from_source(wal.as_slice()).unwrap();
+        let header = *index.header().unwrap();
+        assert_eq!(header.max_frame, wal_reader.frame_count());
+        assert!(header.max_frame as usize > HASH_TABLE_PAGES_FIRST + HASH_TABLE_PAGES);
+        assert_eq!(header.page_size, 512);
+        assert_eq!(index.checkpoint.backfill, 0);
+        assert!(index.is_consistent_with(&wal_reader).unwrap());
+
+        let mut latest = HashMap::new();
+        for frame in wal_reader.frames() {
+            let frame = frame.unwrap();
+            assert_eq!(index.page_no(frame.frame_no), Some(frame.header.page_no));
+            latest.insert(frame.header.page_no, frame.frame_no);
+        }
+        for page_no in 1..=header.db_size {
+            assert_eq!(
+                index.frame_no(page_no, header.max_frame),
+                latest.get(&page_no).copied()
+            );
+        }
+        // earlier versions are found when looking as of an earlier frame
+        let earlier = index.frame_no(2, header.max_frame / 2).unwrap();
+        assert!(earlier <= header.max_frame / 2);
+        assert_eq!(
+            wal_reader.frame(earlier).unwrap().unwrap().header.page_no,
+            2
+        );
+
+        let rows = |reader: &Reader<Vec<u8>>| -> Vec<i64> {
+            reader.table_rows(2).map(|row| row.unwrap().0).collect()
+        };
+        let scanned = Reader::from_source_with_wal(main.clone(), wal.clone()).unwrap();
+        let indexed =
+            Reader::from_source_with_wal_index(main.clone(), wal.clone(), shm.clone()).unwrap();
+        assert!(!scanned.uses_wal_index());
+        assert!(indexed.uses_wal_index());
+        assert_eq!(rows(&indexed), (0..2000).collect::<Vec<i64>>());
+        assert_eq!(rows(&indexed), rows(&scanned));
+        assert_eq!(indexed.wal_commits(), scanned.wal_commits());
+        assert_eq!(indexed.header.db_size, scanned.header.db_size);
+
+        // the copies of the header disagree, as if a writer was interrupted
+        let mut torn = shm.clone();
+        torn[WAL_INDEX_HEADER_SIZE + 20] ^= 1;
+        let reader = Reader::from_source_with_wal_index(main.clone(), wal.clone(), torn).unwrap();
+        assert!(!reader.uses_wal_index());
+        assert_eq!(rows(&reader), rows(&scanned));
+
+        let reader = Reader::from_source_with_wal_index(main, wal, vec![0; 136]).unwrap();
+        assert!(!reader.uses_wal_index());
+    }
+
+    #[test]
+    fn fall_back_to_scan_with_stale_wal_index() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stale.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE test (id INTEGER PRIMARY KEY);",
+        )
+        .unwrap();
+        conn.execute("INSERT INTO test VALUES (1)", ()).unwrap();
+        let stale_shm = std::fs::read(dir.path().join("stale.sqlite3-shm")).unwrap();
+        conn.execute("INSERT INTO test VALUES (2)", ()).unwrap();
+        conn.execute("INSERT INTO test VALUES (3)", ()).unwrap();
+
+        let main = std::fs::read(&path).unwrap();
+        let wal = std::fs::read(dir.path().join("stale.sqlite3-wal")).unwrap();
+        conn.close().unwrap();
+
+        let index = WalIndex::from_source(stale_shm.as_slice()).unwrap();
+        let wal_reader = WalReader::from_source(wal.as_slice()).unwrap();
+        assert!(index.header().unwrap().max_frame < wal_reader.frame_count());
+        assert!(!index.is_consistent_with(&wal_reader).unwrap());
+
+        let reader = Reader::from_source_with_wal_index(main, wal, stale_shm).unwrap();
+        assert!(!reader.uses_wal_index());
+        assert_eq!(reader.wal_commits().len(), 4);
+        let rows: Vec<i64> = reader.table_rows(2).map(|row| row.unwrap().0).collect();
+        assert_eq!(rows, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn read_rolled_back_journal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = PERSIST;
+            PRAGMA cache_size = 5;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50)
+            INSERT INTO test SELECT i, printf('%.500c', 'a') FROM n;",
+        )
+        .unwrap();
+        let before = std::fs::read(&path).unwrap();
+        let journal_path = dir.path().join("journal.sqlite3-journal");
+
+        // a committed persistent journal has its header zeroed
+        let committed = std::fs::read(&journal_path).unwrap();
+        assert!(!is_hot_journal(&committed));
+        let reader = Reader::from_source_with_journal(before.clone(), committed).unwrap();
+        assert_eq!(reader.table_rows(2).count(), 50);
+
+        // small cache makes the transaction spill changed pages into the main file
+        conn.execute_batch(
+            "BEGIN;
+            UPDATE test SET foo = printf('%.500c', 'b');
+            WITH RECURSIVE n(i) AS (SELECT 51 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+            INSERT INTO test SELECT i, printf('%.500c', 'c') FROM n;",
+        )
+        .unwrap();
+        let main = std::fs::read(&path).unwrap();
+        let journal = std::fs::read(&journal_path).unwrap();
+        conn.execute_batch("ROLLBACK;").unwrap();
+        conn.close().unwrap();
+        assert_ne!(main[..before.len()], before[..]);
+
+        let journal_reader = JournalReader::from_source(journal.as_slice()).unwrap();
+        assert!(journal_reader.is_hot());
+        assert_eq!(journal_reader.header.page_size, 4096);
+        assert_eq!(
+            journal_reader.header.initial_db_size as usize,
+            before.len() / 4096
+        );
+        assert!(journal_reader.pages().all(|p| p.is_ok()));
+
+        let foo = |reader: &Reader<Vec<u8>>| -> Vec<String> {
+            let mut rows = vec![];
+            for row in reader.table_rows(2) {
+                match &row.unwrap().1.column_values[1] {
+                    Some(Payload::Text(foo)) => rows.push(reader.decode_text(foo)),
+                    _ => unreachable!("foo should be text"),
+                }
+            }
+            rows
+        };
+
+        let original = Reader::from_source(before).unwrap();
+        let rolled_back = Reader::from_source_with_journal(main.clone(), journal.clone()).unwrap();
+        assert!(rolled_back.journal_error().is_none());
+        assert_eq!(rolled_back.header.db_size, original.header.db_size);
+        assert_eq!(foo(&rolled_back), foo(&original));
+        assert!(matches!(
+            rolled_back.get_page(original.header.db_size),
+            Err(SQLiteError::PageOutOfBoundsError(_))
+        ));
+
+        // rolling back stops at the first record with a wrong checksum
+        let mut corrupted = journal.clone();
+        let second_record = 512 + 4 + 4096 + 4;
+        corrupted[second_record + 4 + 4096] ^= 0xff;
+        let reader = Reader::from_source_with_journal(main.clone(), corrupted).unwrap();
+        assert!(matches!(
+            reader.journal_error(),
+            Some(SQLiteError::InvalidJournalPageError(offset)) if *offset == second_record
+        ));
+
+        let mut other_page_size = journal;
+        other_page_size[24..28].copy_from_slice(&1024u32.to_be_bytes());
+        assert!(matches!(
+            Reader::from_source_with_journal(main, other_page_size),
+            Err(SQLiteError::JournalPageSizeError(1024))
+        ));
+    }
+
+    #[test]
+    fn carve_deleted_records() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("carve.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA secure_delete = OFF;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT, n INTEGER);
+            WITH RECURSIVE ids(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM ids WHERE id < 10)
+            INSERT INTO test SELECT id, 'name-' || id, id * 1000 FROM ids;
+            INSERT INTO test SELECT column1, 'name-' || column1, column1 * 1000
+            FROM (VALUES (200), (201), (11), (12));
+            DELETE FROM test WHERE id IN (5, 200, 12);",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let records: Vec<CarvedRecord> = reader.carve().unwrap().map(|r| r.unwrap()).collect();
+        let carved = |name: &str| {
+            records.iter().find(|r| {
+                r.payload
+                    .column_values
+                    .iter()
+                    .any(|v| matches!(v, Some(Payload::Text(t)) if reader.decode_text(t) == name))
+            })
+        };
+
+        // freeblock header overwrote the payload size, the 2-byte rowid and the header size
+        let freed = carved("name-200").unwrap();
+        assert_eq!(freed.page_no, 2);
+        assert_eq!(freed.free_space, FreeSpace::Freeblock);
+        assert_eq!(freed.confidence, Confidence::Low);
+        assert_eq!(freed.rowid, None);
+        assert_eq!(
+            freed.payload.column_values,
+            vec![None, Some("name-200".into()), Some(Payload::I32(200000))]
+        );
+
+        // with a 1-byte rowid the first serial type is lost too
+        let freed = carved("name-5").unwrap();
+        assert_eq!(freed.free_space, FreeSpace::Freeblock);
+        assert_eq!(freed.confidence, Confidence::Low);
+        assert_eq!(
+            freed.payload.column_values[freed.payload.column_values.len() - 2..],
+            [Some("name-5".into()), Some(Payload::I16(5000))]
+        );
+
+        // deleted at the start of the content area, which then shrinks over it
+        let last = carved("name-12").unwrap();
+        assert_eq!(last.free_space, FreeSpace::Unallocated);
+        assert_eq!(last.confidence, Confidence::Low);
+
+        // neither live rows nor stale cell pointers are mistaken for records
+        assert_eq!(records.len(), 3);
+        for live in ["name-4", "name-6", "name-201", "name-11"] {
+            assert!(carved(live).is_none());
+        }
+    }
+
+    #[test]
+    fn carve_freelist_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("freelist.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA secure_delete = OFF;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+            INSERT INTO test SELECT i, 'row-' || i || printf('%.200c', '.') FROM n;
+            DROP TABLE test;",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        assert!(reader.schema().unwrap().tables().next().is_none());
+        let free_pages = reader.free_pages().unwrap();
+
+        let records: Vec<CarvedRecord> = reader
+            .carve_freelist()
+            .unwrap()
+            .map(|r| r.unwrap())
+            .filter(|r| r.free_space == FreeSpace::FreePage)
+            .collect();
+        for record in records.iter() {
+            assert!(free_pages.contains(&record.page_no));
+            assert_eq!(record.confidence, Confidence::High);
+
+            let rowid = record.rowid.unwrap();
+            match &record.payload.column_values[1] {
+                Some(Payload::Text(foo)) => {
+                    assert!(reader
+                        .decode_text(foo)
+                        .starts_with(&format!("row-{rowid}.")))
+                }
+                _ => unreachable!("foo should be text"),
+            }
+        }
+
+        // only the trunk page lost its rows
+        let rowids: BTreeSet<i64> = records.iter().filter_map(|r| r.rowid).collect();
+        assert_eq!(rowids.len(), records.len());
+        assert!(rowids.len() > 80, "{} rows recovered", rowids.len());
+    }
+
+    #[test]
+    fn check_integrity() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("integrity.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT, bar BLOB);
+            CREATE INDEX test_foo ON test (foo COLLATE NOCASE);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO test SELECT i, printf('%s-%d%.100c', char(65 + i % 26 + i % 2 * 32), i, '.'),
+                CASE WHEN i % 50 = 0 THEN zeroblob(10000) END FROM n;
+            DELETE FROM test WHERE id BETWEEN 100 AND 180 OR id % 7 = 0;",
+        )
+        .unwrap();
+        let pragma: String = conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pragma, "ok");
+        conn.close().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let reader = Reader::from_source(buf.clone()).unwrap();
+        assert!(reader.header.total_freelist_pages > 0);
+        assert!(reader.integrity_check().is_empty());
+
+        let root_page_no = reader
+            .schema()
+            .unwrap()
+            .table("test")
+            .unwrap()
+            .root_page
+            .unwrap();
+        let (leaf_page_no, rightmost_page_no) = match reader.get_page(root_page_no - 1).unwrap() {
+            Page::InteriorTable(p) => (p.cells[0].left_child_page_no, p.header.rightmost_pointer),
+            _ => unreachable!("table should have more than one page"),
+        };
+        let page_start = |page_no: u32| (page_no as usize - 1) * 4096;
+        let check = |buf: Vec<u8>| Reader::from_source(buf).unwrap().integrity_check();
+
+        let mut corrupted = buf.clone();
+        corrupted[page_start(root_page_no) + 8..][..4].copy_from_slice(&0xffffu32.to_be_bytes());
+        let problems = check(corrupted);
+        assert!(matches!(
+            problems[0],
+            IntegrityProblem::PageOutOfRange { page_no: 0xffff, referenced_by } if referenced_by == root_page_no
+        ));
+        assert!(problems.iter().any(|p| matches!(
+            p,
+            IntegrityProblem::PageNeverUsed { page_no } if *page_no == rightmost_page_no
+        )));
+
+        let mut corrupted = buf.clone();
+        let pointers = page_start(leaf_page_no) + 8;
+        corrupted[pointers..pointers + 4].rotate_left(2);
+        assert!(matches!(
+            check(corrupted)[..],
+            [IntegrityProblem::KeyOutOfOrder { page_no, cell: 1 }] if page_no == leaf_page_no
+        ));
+
+        let mut corrupted = buf.clone();
+        corrupted[page_start(leaf_page_no) + 7] += 1;
+        assert!(matches!(
+            check(corrupted)[..],
+            [IntegrityProblem::FragmentCount { page_no, .. }] if page_no == leaf_page_no
+        ));
+
+        let mut corrupted = buf.clone();
+        corrupted[39] += 1;
+        assert!(matches!(
+            check(corrupted)[..],
+            [IntegrityProblem::FreelistCount { expected, actual }] if expected == actual + 1
+        ));
+    }
+
+    #[test]
+    fn check_integrity_of_pointer_map_and_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("integrity-ptrmap.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo BLOB);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+            INSERT INTO test SELECT i, zeroblob(i * 100) FROM n;
+            DELETE FROM test WHERE id > 90;",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let reader = Reader::from_source(buf.clone()).unwrap();
+        assert!(reader.header.total_freelist_pages > 0);
+        assert!(reader.integrity_check().is_empty());
+
+        // the entry of page 3, the table root, claims it's a free page
+        let mut corrupted = buf.clone();
+        corrupted[4096] = 2;
+        assert!(matches!(
+            Reader::from_source(corrupted).unwrap().integrity_check()[..],
+            [IntegrityProblem::PointerMapMismatch {
+                page_no: 3,
+                expected: PointerMapEntry::RootPage,
+                actual: Some(PointerMapEntry::FreePage),
+            }]
+        ));
+
+        // type of the table entry in sqlite_schema is no longer known
+        let mut corrupted = buf;
+        let at = corrupted.windows(5).position(|w| w == b"table").unwrap();
+        corrupted[at] = b'x';
+        let problems = Reader::from_source(corrupted).unwrap().integrity_check();
+        assert!(matches!(
+            problems[0],
+            IntegrityProblem::UnreadableSchema { .. }
+        ));
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, IntegrityProblem::PageNeverUsed { page_no: 3 })));
+        assert!(!problems
+            .iter()
+            .any(|p| matches!(p, IntegrityProblem::FreelistCount { .. })));
+    }
+
+    #[test]
+    fn count_pages_with_stale_db_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stale-size.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            CREATE INDEX test_foo ON test (foo);
+            INSERT INTO test VALUES (1, 'foo');",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let reader = Reader::from_source(buf.clone()).unwrap();
+        assert_eq!(reader.page_count(), 3);
+
+        // version-valid-for no longer matches the change counter, the size is stale
+        let mut stale = buf.clone();
+        stale[28..32].copy_from_slice(&0x7fffffffu32.to_be_bytes());
+        let reader = Reader::from_source(stale).unwrap();
+        assert_eq!(reader.header.db_size, 0x7fffffff);
+        assert_eq!(reader.page_count(), 3);
+        assert!(reader.integrity_check().is_empty());
+
+        // a valid size can't go past the end of the file
+        let mut valid = buf.clone();
+        valid[28..32].copy_from_slice(&10u32.to_be_bytes());
+        valid.copy_within(24..28, 92);
+        assert_eq!(Reader::from_source(valid).unwrap().page_count(), 3);
+
+        let mut valid = buf;
+        valid[28..32].copy_from_slice(&2u32.to_be_bytes());
+        valid.copy_within(24..28, 92);
+        assert_eq!(Reader::from_source(valid).unwrap().page_count(), 2);
+    }
+
+    #[test]
+    fn check_integrity_of_collated_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("collated.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE nocase_pk (k TEXT COLLATE NOCASE PRIMARY KEY, v) WITHOUT ROWID;
+            CREATE TABLE desc_pk (k, v, PRIMARY KEY (k DESC)) WITHOUT ROWID;
+            CREATE TABLE nocase_unique (k TEXT COLLATE NOCASE UNIQUE, v);
+            CREATE INDEX nocase_expr ON nocase_unique (k || v);",
+        )
+        .unwrap();
+        for table in ["nocase_pk", "desc_pk", "nocase_unique"] {
+            conn.execute_batch(&format!(
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+                INSERT INTO {table} SELECT printf('%s-%d', char(65 + i % 26 + i % 2 * 32), i), i
+                FROM n;"
+            ))
+            .unwrap();
+        }
+        let pragma: String = conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pragma, "ok");
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let problems = reader.integrity_check();
+        assert!(
+            problems.is_empty(),
+            "{:?}",
+            &problems[..problems.len().min(5)]
+        );
+    }
+
+    #[test]
+    fn map_page_owners() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ownership.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+            CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT, bar BLOB);
+            CREATE INDEX test_foo ON test (foo);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO test SELECT i, printf('foo-%d%.100c', i, '.'),
+                CASE WHEN i % 50 = 0 THEN zeroblob(10000) END FROM n;
+            DELETE FROM test WHERE id BETWEEN 100 AND 180;",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let reader = Reader::from_source(buf.clone()).unwrap();
+        let schema = reader.schema().unwrap();
+        let table_root = schema.table("test").unwrap().root_page.unwrap();
+        let index_root = schema.index("test_foo").unwrap().root_page.unwrap();
+        let map = reader.page_map().unwrap();
+
+        assert_eq!(
+            map.get(1),
+            Some(&PageOwner::BTree {
+                root_page_no: 1,
+                kind: Some(BTreePageKind::LeafTable),
+            })
+        );
+        assert_eq!(map.get(2), Some(&PageOwner::PointerMap));
+        assert_eq!(
+            map.get(table_root),
+            Some(&PageOwner::BTree {
+                root_page_no: table_root,
+                kind: Some(BTreePageKind::InteriorTable),
+            })
+        );
+        assert_eq!(
+            map.get(index_root),
+            Some(&PageOwner::BTree {
+                root_page_no: index_root,
+                kind: Some(BTreePageKind::InteriorIndex),
+            })
+        );
+        assert_eq!(map.name(1), Some("sqlite_schema"));
+        assert_eq!(map.name(table_root), Some("test"));
+        assert_eq!(map.name(index_root), Some("test_foo"));
+        assert!(map.iter().any(|(_, owner)| matches!(
+            owner,
+            PageOwner::Overflow { root_page_no } if *root_page_no == table_root
+        )));
+        let free_pages = map
+            .iter()
+            .filter(|(_, owner)| {
+                matches!(owner, PageOwner::FreelistTrunk | PageOwner::FreelistLeaf)
+            })
+            .count();
+        assert_eq!(free_pages, reader.header.total_freelist_pages as usize);
+        assert_eq!(map.orphaned().count(), 0);
+        assert_eq!(
+            map.iter().count(),
+            reader.header.db_size as usize,
+            "every page has an owner"
+        );
+
+        // stale size in the header, pages are counted from the file length
+        let mut stale = buf.clone();
+        stale[28..32].copy_from_slice(&(reader.header.db_size + 100).to_be_bytes());
+        let stale_map = Reader::from_source(stale).unwrap().page_map().unwrap();
+        assert_eq!(stale_map.iter().count(), buf.len() / 4096);
+        assert_eq!(stale_map.orphaned().count(), 0);
+
+        let rightmost_page_no = match reader.get_page(table_root - 1).unwrap() {
+            Page::InteriorTable(p) => p.header.rightmost_pointer,
+            _ => unreachable!("table should have more than one page"),
+        };
+        let mut corrupted = buf;
+        corrupted[(table_root as usize - 1) * 4096 + 8..][..4].copy_from_slice(&0u32.to_be_bytes());
+        let map = Reader::from_source(corrupted).unwrap().page_map().unwrap();
+        assert_eq!(map.get(rightmost_page_no), Some(&PageOwner::Orphaned));
+        assert!(!map.pages_of(table_root).any(|p| p == rightmost_page_no));
+    }
+
+    #[test]
+    fn iterate_table_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rows.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT)", ())
+            .unwrap();
+        for id in (0..3000).rev() {
+            let foo = if id % 100 == 0 {
+                format!("{}", id).repeat(2000)
+            } else {
+                format!("{}", id)
+            };
+            conn.execute("INSERT INTO test VALUES (?1, ?2)", (id, foo))
+                .unwrap();
+        }
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        assert!(matches!(
+            reader.get_page(1).unwrap(),
+            Page::InteriorTable(_)
+        ));
+
+        let mut count = 0;
+        for (expected_id, row) in reader.table_rows(2).enumerate() {
+            let (rowid, payload) = row.unwrap();
+            let expected_foo = if expected_id % 100 == 0 {
+                format!("{}", expected_id).repeat(2000)
+            } else {
+                format!("{}", expected_id)
+            };
+
+            assert_eq!(rowid, expected_id as i64);
+            assert_eq!(
+                payload.column_values,
+                vec![None, Some(expected_foo.as_str().into())]
+            );
+            count += 1;
+        }
+        assert_eq!(count, 3000);
+
+        let schema: Vec<_> = reader.table_rows(1).map(Result::unwrap).collect();
+        assert_eq!(schema.len(), 1);
+    }
+
+    #[test]
+    fn iterate_non_table_page() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("index.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            CREATE INDEX test_foo ON test (foo);",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let mut rows = reader.table_rows(3);
+
+        assert!(matches!(
+            rows.next(),
+            Some(Err(SQLiteError::UnexpectedPageTypeError(3)))
+        ));
+        assert!(rows.next().is_none());
+        assert!(matches!(
+            reader.table_rows(0).next(),
+            Some(Err(SQLiteError::PageOutOfBoundsError(0)))
+        ));
+    }
+
+    #[test]
+    fn seek_index_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("seek.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, score, tag TEXT);
+            CREATE INDEX test_name ON test (name);
+            CREATE INDEX test_score ON test (score DESC, tag COLLATE RTRIM);
+            BEGIN;",
+        )
+        .unwrap();
+        for id in 0..3000i64 {
+            let name = match id % 3 {
+                0 => format!("name{}", id % 50),
+                1 => format!("NAME{}", id % 50),
+                _ => format!("Name{}", id % 50).repeat(300),
+            };
+            let score: rusqlite::types::Value = match id % 4 {
+                0 => (id % 30).into(),
+                1 => ((id % 30) as f64).into(),
+                2 => ((id % 30) as f64 + 0.5).into(),
+                _ => rusqlite::types::Value::Null,
+            };
+            let tag = format!("x{}{}", id % 3, " ".repeat(id as usize % 3));
+            conn.execute(
+                "INSERT INTO test VALUES (?1, ?2, ?3, ?4)",
+                (id, name, score, tag),
+            )
+            .unwrap();
+        }
+        conn.execute_batch("COMMIT;").unwrap();
+
+        let query = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> Vec<i64> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let ids = stmt
+                .query_map(params, |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            ids
+        };
+        let by_name = query("SELECT id FROM test WHERE name = 'name7' ORDER BY id", &[]);
+        let by_score = query(
+            "SELECT id FROM test WHERE score = 7 ORDER BY tag COLLATE RTRIM, id",
+            &[],
+        );
+        let by_score_and_tag = query(
+            "SELECT id FROM test WHERE score = 7.5 AND tag = 'x1' ORDER BY id",
+            &[],
+        );
+        let by_null_score = query(
+            "SELECT id FROM test WHERE score IS NULL ORDER BY tag COLLATE RTRIM, id",
+            &[],
+        );
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+        let name_root = schema.index("test_name").unwrap().root_page.unwrap();
+        let score_root = schema.index("test_score").unwrap().root_page.unwrap();
+
+        assert!(by_name.len() > 30);
+        assert_eq!(
+            reader
+                .index_seek(name_root, &[Some("NaMe7".into())])
+                .unwrap(),
+            by_name
+        );
+        assert_eq!(
+            reader.index_seek(score_root, &[Some(7i8.into())]).unwrap(),
+            by_score
+        );
+        assert_eq!(
+            reader
+                .index_seek(score_root, &[Some(7.5f64.into()), Some("x1  ".into())])
+                .unwrap(),
+            by_score_and_tag
+        );
+        assert_eq!(
+            reader.index_seek(score_root, &[None]).unwrap(),
+            by_null_score
+        );
+        assert!(reader
+            .index_seek(name_root, &[Some("missing".into())])
+            .unwrap()
+            .is_empty());
+
+        let nocase = KeyColumn {
+            collation: Collation::NoCase,
+            ..KeyColumn::default()
+        };
+        assert_eq!(
+            reader
+                .index_seek_with(name_root, &[Some("nAmE7".into())], &[nocase])
+                .unwrap(),
+            by_name
+        );
+        assert_eq!(reader.index_seek(name_root, &[]).unwrap().len(), 3000);
+    }
+
+    #[test]
+    fn resolve_key_columns() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keys.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE UNIQUE);
+            CREATE TABLE pairs (k TEXT COLLATE NOCASE, v, PRIMARY KEY (k DESC)) WITHOUT ROWID;
+            CREATE INDEX pairs_v ON pairs (v);
+            CREATE INDEX pairs_expr ON pairs (v || k);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+            INSERT INTO test SELECT i, printf('name%d', i) FROM n;",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+        let root = |name: &str| {
+            let entry = schema.table(name).or_else(|| schema.index(name));
+            entry.unwrap().root_page.unwrap()
+        };
+        let nocase = KeyColumn {
+            collation: Collation::NoCase,
+            order: SortOrder::Asc,
+        };
+        let nocase_desc = KeyColumn {
+            order: SortOrder::Desc,
+            ..nocase
+        };
+
+        let autoindex = root("sqlite_autoindex_test_1");
+        assert_eq!(reader.key_columns(autoindex).unwrap(), vec![nocase]);
+        assert_eq!(
+            reader
+                .index_seek(autoindex, &[Some("NaMe7".into())])
+                .unwrap(),
+            vec![7]
+        );
+
+        assert!(reader.key_columns(root("test")).unwrap().is_empty());
+        assert_eq!(
+            reader.key_columns(root("pairs")).unwrap(),
+            vec![nocase_desc]
+        );
+        assert_eq!(
+            reader.key_columns(root("pairs_v")).unwrap(),
+            vec![KeyColumn::default(), nocase_desc]
+        );
+        assert!(matches!(
+            reader.key_columns(root("pairs_expr")),
+            Err(SQLiteError::KeyColumnsError(_))
+        ));
+    }
+
+    #[test]
+    fn decode_rows_like_select() {
+        use rusqlite::types::Value;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("decoded.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            INSERT INTO test VALUES (1, 'one'), (2, 'two');
+            ALTER TABLE test ADD COLUMN bar INTEGER DEFAULT -7;
+            ALTER TABLE test ADD COLUMN baz TEXT DEFAULT 'missing';
+            ALTER TABLE test ADD COLUMN qux BLOB DEFAULT x'cafe';
+            ALTER TABLE test ADD COLUMN quux REAL DEFAULT 0.5;
+            ALTER TABLE test ADD COLUMN extra;
+            INSERT INTO test VALUES (3, 'three', 3, 'present', x'00', 1.5, 'something');
+            INSERT INTO test (foo) VALUES ('four');",
+        )
+        .unwrap();
+
+        let mut stmt = conn.prepare("SELECT * FROM test ORDER BY id").unwrap();
+        let expected: Vec<Vec<Value>> = stmt
+            .query_map((), |row| (0..7).map(|i| row.get(i)).collect())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        drop(stmt);
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+
+        let to_value = |value: Option<Payload>| match value {
+            None => Value::Null,
+            Some(Payload::F64(v)) => Value::Real(v),
+            Some(Payload::Text(t)) => Value::Text(reader.decode_text(&t)),
+            Some(Payload::Blob(b)) => Value::Blob(b.into_owned()),
+            Some(v) => Value::Integer(v.as_i64().unwrap()),
+        };
+        let rows: Vec<Vec<Value>> = reader
+            .decoded_rows(schema.table("test").unwrap())
+            .unwrap()
+            .map(|row| row.unwrap().1.into_iter().map(to_value).collect())
+            .collect();
+
+        assert_eq!(rows, expected);
+        assert_eq!(rows[1][0], Value::Integer(2));
+        assert_eq!(rows[1][2], Value::Integer(-7));
+    }
+
+    #[test]
+    fn convert_defaults_to_column_affinity() {
+        use rusqlite::types::Value;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("affinity.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY);
+            INSERT INTO test VALUES (1);
+            ALTER TABLE test ADD COLUMN a INTEGER DEFAULT '5';
+            ALTER TABLE test ADD COLUMN b DEFAULT ((3));
+            ALTER TABLE test ADD COLUMN c TEXT DEFAULT 5;
+            ALTER TABLE test ADD COLUMN d REAL DEFAULT (-2);
+            ALTER TABLE test ADD COLUMN e NUMERIC DEFAULT '1.0';
+            ALTER TABLE test ADD COLUMN f INTEGER DEFAULT 2.5;
+            ALTER TABLE test ADD COLUMN g VARCHAR DEFAULT 0.5;
+            ALTER TABLE test ADD COLUMN h TEXT DEFAULT 1e20;
+            ALTER TABLE test ADD COLUMN i TEXT DEFAULT 1.50;
+            ALTER TABLE test ADD COLUMN j TEXT DEFAULT 9223372036854775808;
+            ALTER TABLE test ADD COLUMN k DEFAULT 1.0;
+            ALTER TABLE test ADD COLUMN l TEXT DEFAULT 007;
+            ALTER TABLE test ADD COLUMN m TEXT DEFAULT - 2.50;
+            ALTER TABLE test ADD COLUMN n TEXT DEFAULT +1.50;
+            ALTER TABLE test ADD COLUMN o TEXT DEFAULT 0x10;
+            ALTER TABLE test ADD COLUMN p INTEGER DEFAULT 0x80000000;
+            ALTER TABLE test ADD COLUMN q TEXT DEFAULT TRUE;
+            ALTER TABLE test ADD COLUMN r REAL DEFAULT FALSE;
+            ALTER TABLE test ADD COLUMN s NUMERIC DEFAULT 9223372036854775808;
+            ALTER TABLE test ADD COLUMN t DEFAULT .5e1;",
+        )
+        .unwrap();
+        let expected: Vec<Value> = conn
+            .query_row("SELECT * FROM test", (), |row| {
+                (0..21).map(|i| row.get(i)).collect()
+            })
+            .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+        let (_, row) = reader
+            .decoded_rows(schema.table("test").unwrap())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let row: Vec<Value> = row
+            .into_iter()
+            .map(|value| match value {
+                None => Value::Null,
+                Some(Payload::F64(v)) => Value::Real(v),
+                Some(Payload::Text(t)) => Value::Text(reader.decode_text(&t)),
+                Some(Payload::Blob(b)) => Value::Blob(b.into_owned()),
+                Some(v) => Value::Integer(v.as_i64().unwrap()),
+            })
+            .collect();
+
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn iterate_without_rowid_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("without_rowid.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (body TEXT, id INTEGER, kind TEXT, PRIMARY KEY (kind, id)) WITHOUT ROWID;
+            CREATE TABLE plain (id INTEGER PRIMARY KEY);
+            BEGIN;",
+        )
+        .unwrap();
+        for id in 0..2000 {
+            let body = format!("body {id}").repeat(if id % 100 == 0 { 500 } else { 3 });
+            conn.execute(
+                "INSERT INTO test VALUES (?1, ?2, ?3)",
+                (body, id, format!("kind{}", id % 7)),
+            )
+            .unwrap();
+        }
+        conn.execute_batch("COMMIT;").unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT body, id, kind FROM test ORDER BY kind, id")
+            .unwrap();
+        let expected: Vec<(String, i64, String)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        drop(stmt);
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+        let table = schema.table("test").unwrap();
+
+        let text = |value: &Option<Payload>| match value {
+            Some(Payload::Text(t)) => reader.decode_text(t),
+            _ => unreachable!("column should be text"),
+        };
+        let rows: Vec<(String, i64, String)> = reader
+            .without_rowid_rows(table)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                (
+                    text(&row[0]),
+                    row[1].as_ref().unwrap().as_i64().unwrap(),
+                    text(&row[2]),
+                )
+            })
+            .collect();
+        assert_eq!(rows, expected);
+
+        // the key is the whole record, there is no rowid
+        let entry = reader
+            .index_entries(table.root_page.unwrap())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.column_values.len(), 3);
+        assert_eq!(entry.rowid(), None);
+
+        assert!(reader
+            .without_rowid_rows(schema.table("plain").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn iterate_index_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("index.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT);
+            CREATE INDEX test_foo ON test (foo);",
+        )
+        .unwrap();
+        for id in 0..3000 {
+            let foo = if id % 100 == 0 {
+                format!("{}", id * 7919 % 3000).repeat(1000)
+            } else {
+                format!("{}", id * 7919 % 3000)
+            };
+            conn.execute("INSERT INTO test VALUES (?1, ?2)", (id, foo))
+                .unwrap();
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT foo, id FROM test ORDER BY foo, id")
+            .unwrap();
+        let expected: Vec<(String, i64)> = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        drop(stmt);
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        assert!(matches!(
+            reader.get_page(2).unwrap(),
+            Page::InteriorIndex(_)
+        ));
+
+        let entries: Vec<(String, i64)> = reader
+            .index_entries(3)
+            .map(|entry| {
+                let entry = entry.unwrap();
+                match &entry.column_values[0] {
+                    Some(Payload::Text(foo)) => (reader.decode_text(foo), entry.rowid().unwrap()),
+                    _ => unreachable!("indexed column should be text"),
+                }
+            })
+            .collect();
+
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn read_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("schema.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT UNIQUE);
+            CREATE INDEX test_foo_id ON test (foo, id);
+            CREATE VIEW test_view AS SELECT foo FROM test;
+            CREATE TRIGGER test_trigger AFTER INSERT ON test BEGIN SELECT 1; END;",
+        )
+        .unwrap();
+        // make the schema span multiple pages
+        for no in 0..200 {
+            conn.execute(
+                &format!("CREATE TABLE filler_{} (id INTEGER PRIMARY KEY)", no),
+                (),
+            )
+            .unwrap();
+        }
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        assert!(matches!(
+            reader.get_page(0).unwrap(),
+            Page::InteriorTable(_)
+        ));
+
+        let schema = reader.schema().unwrap();
+        assert_eq!(schema.entries.len(), 205);
+        assert_eq!(schema.tables().count(), 201);
+
+        let table = schema.table("TEST").unwrap();
+        assert_eq!(table.kind, SchemaEntryKind::Table);
+        assert_eq!(table.tbl_name, "test");
+        assert_eq!(table.root_page, Some(2));
+        assert_eq!(
+            table.sql.as_deref(),
+            Some("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT UNIQUE)")
+        );
+
+        let indexes: Vec<_> = schema.indexes_of("test").map(|e| e.name.as_str()).collect();
+        assert_eq!(indexes, vec!["sqlite_autoindex_test_1", "test_foo_id"]);
+        assert_eq!(schema.index("sqlite_autoindex_test_1").unwrap().sql, None);
+
+        assert_eq!(schema.view("test_view").unwrap().root_page, None);
+        assert_eq!(schema.trigger("test_trigger").unwrap().tbl_name, "test");
+        assert_eq!(schema.by_root_page(2), Some(table));
+        assert!(schema.table("test_view").is_none());
+        assert!(schema.table("filler_199").is_some());
+    }
+
+    #[test]
+    fn name_columns_from_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ddl.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE test (
+                id INTEGER PRIMARY KEY,
+                foo TEXT NOT NULL COLLATE NOCASE,
+                bar REAL DEFAULT 1.5
+            );
+            CREATE INDEX test_bar ON test (bar DESC);
+            INSERT INTO test VALUES (1, 'tjena', 2.5);",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        let schema = reader.schema().unwrap();
+
+        let table = schema.table("test").unwrap().table_definition().unwrap();
+        assert_eq!(table.rowid_alias, Some(0));
+        assert_eq!(table.columns[1].collation.as_deref(), Some("NOCASE"));
+        assert_eq!(
+            table.columns[2].default,
+            Some(DefaultValue::Real {
+                value: 1.5,
+                text: Some("1.5".into())
+            })
+        );
+
+        let (_, payload) = reader.table_rows(2).next().unwrap().unwrap();
+        assert_eq!(
+            table.named(payload.column_values),
+            vec![
+                ("id", None),
+                ("foo", Some("tjena".into())),
+                ("bar", Some(2.5f64.into()))
+            ]
+        );
+
+        let index = schema
+            .index("test_bar")
+            .unwrap()
+            .index_definition()
+            .unwrap();
+        assert_eq!(index.table, "test");
+        assert_eq!(index.columns[0].order, SortOrder::Desc);
+        assert!(schema
+            .index("test_bar")
+            .unwrap()
+            .table_definition()
+            .is_err());
+    }
+
+    #[test]
+    fn lookup_row_by_rowid() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("lookup.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT)", ())
+            .unwrap();
+        conn.execute_batch("BEGIN").unwrap();
+        for id in 0..10_000 {
+            let foo = if id == 4242 {
+                "x".repeat(10_000)
+            } else {
+                format!("{}", id)
+            };
+            conn.execute("INSERT INTO test VALUES (?1, ?2)", (id * 2, foo))
+                .unwrap();
+        }
+        conn.execute_batch("COMMIT").unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+
+        for id in [0, 1, 777, 4999, 9999] {
+            let row = reader.get_row(2, id * 2).unwrap().unwrap();
+            assert_eq!(
+                row.column_values,
+                vec![None, Some(format!("{}", id).as_str().into())]
+            );
+        }
+
+        let long_row = reader.get_row(2, 4242 * 2).unwrap().unwrap();
+        assert_eq!(
+            long_row.column_values[1],
+            Some("x".repeat(10_000).as_str().into())
+        );
+
+        for missing in [-1, 1, 777, 20_000, i64::MIN, i64::MAX] {
+            assert!(reader.get_row(2, missing).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn scan_rowid_ranges() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ranges.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, foo TEXT)", ())
+            .unwrap();
+        conn.execute_batch("BEGIN").unwrap();
+        for id in 0..5_000 {
+            conn.execute("INSERT INTO test VALUES (?1, ?2)", (id * 3, "tjena"))
+                .unwrap();
+        }
+        conn.execute_batch("COMMIT").unwrap();
+        conn.close().unwrap();
+
+        let reader = Reader::open_readfile(&path).unwrap();
+        fn rowids<'a>(
+            rows: impl Iterator<Item = Result<(i64, TableCellPayload<'a>), SQLiteError>>,
+        ) -> Vec<i64> {
+            rows.map(|row| row.unwrap().0).collect()
+        }
+
+        assert_eq!(
+            rowids(reader.table_range(2, 1000..1010)),
+            vec![1002, 1005, 1008]
+        );
+        assert_eq!(
+            rowids(reader.table_range(2, 1002..=1008)),
+            vec![1002, 1005, 1008]
+        );
+        assert_eq!(
+            rowids(reader.table_range(2, (Bound::Excluded(14_991), Bound::Unbounded))),
+            vec![14_994, 14_997]
+        );
+        assert_eq!(rowids(reader.table_range(2, ..3)), vec![0]);
+        assert!(rowids(reader.table_range(2, 15_000..)).is_empty());
+        assert!(rowids(reader.table_range(2, 1..3)).is_empty());
+        assert_eq!(reader.table_range(2, 7_500..).count(), 2_500);
+
+        assert_eq!(
+            rowids(reader.table_range_rev(2, 1000..=1008)),
+            vec![1008, 1005, 1002]
+        );
+        assert_eq!(
+            rowids(reader.table_range_rev(2, ..)),
+            rowids(reader.table_rows(2))
+                .into_iter()
+                .rev()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            rowids(reader.table_rows_rev(2).take(3)),
+            vec![14_997, 14_994, 14_991]
+        );
+        assert_eq!(rowids(reader.table_range_rev(2, ..=1)), vec![0]);
+        assert!(rowids(reader.table_range_rev(2, ..0)).is_empty());
+        assert_eq!(reader.table_range_rev(2, 7_500..).count(), 2_500);
+    }
 }
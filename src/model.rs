@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
 use crate::error::SQLiteError;
 
 pub struct Database<'a> {
@@ -9,6 +12,8 @@ pub struct DbHeader {
     pub page_size: PageSize,
     pub write_version: u8,
     pub read_version: u8,
+    /// Bytes at the end of every page used by extensions (checksums, encryption)
+    pub reserved_bytes: u8,
     pub max_payload_fraction: u8,
     pub min_payload_fraction: u8,
     pub leaf_payload_fraction: u8,
@@ -28,8 +33,121 @@ pub struct DbHeader {
     pub sqlite_version_number: u32,
 }
 
+impl DbHeader {
+    /// Page size without the reserved bytes at the end of each page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size.real_size() - self.reserved_bytes as usize
+    }
+
+    /// Full and incremental auto-vacuum databases keep pointer-map pages.
+    pub fn is_auto_vacuum(&self) -> bool {
+        self.no_largest_root_b_tree != 0
+    }
+
+    /// Auto-vacuum only happens on `PRAGMA incremental_vacuum`.
+    pub fn is_incremental_vacuum(&self) -> bool {
+        self.is_auto_vacuum() && self.incremental_vacuum_mode != 0
+    }
+
+    /// The page containing the 1GiB offset, it is never used by SQLite.
+    pub fn lock_byte_page_no(&self) -> u32 {
+        (0x4000_0000 / self.page_size.real_size()) as u32 + 1
+    }
+
+    /// Number of the pointer-map page holding the entry for the given page.
+    /// `None` when the database is not auto-vacuumed or the page has no entry (page 1).
+    pub fn ptrmap_page_no(&self, page_no: u32) -> Option<u32> {
+        if !self.is_auto_vacuum() || page_no < 2 {
+            return None;
+        }
+
+        let pages_per_map_page = (self.usable_size() / POINTER_MAP_ENTRY_SIZE) as u32 + 1;
+        let ptrmap_page_no = (page_no - 2) / pages_per_map_page * pages_per_map_page + 2;
+
+        if ptrmap_page_no == self.lock_byte_page_no() {
+            Some(ptrmap_page_no + 1)
+        } else {
+            Some(ptrmap_page_no)
+        }
+    }
+
+    pub fn is_ptrmap_page(&self, page_no: u32) -> bool {
+        self.ptrmap_page_no(page_no) == Some(page_no)
+    }
+}
+
 pub struct PageSize(pub u16);
 
+/// B-tree page parameters needed to split a cell payload between the page itself and
+/// the overflow pages.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PageLayout {
+    pub usable_size: usize,
+    pub max_payload_fraction: u8,
+    pub min_payload_fraction: u8,
+    pub leaf_payload_fraction: u8,
+}
+
+impl PageLayout {
+    /// Layout with the payload fractions every SQLite version writes (64, 32, 32).
+    pub fn new(usable_size: usize) -> Self {
+        PageLayout {
+            usable_size,
+            max_payload_fraction: 64,
+            min_payload_fraction: 32,
+            leaf_payload_fraction: 32,
+        }
+    }
+
+    /// How many bytes of a table leaf cell payload are stored on the page.
+    pub fn table_leaf_local_size(&self, payload_size: u64) -> usize {
+        let max_local = self.usable_size - 35;
+        let min_local = self.fraction_of_usable(self.leaf_payload_fraction);
+        self.local_size(payload_size, max_local, min_local)
+    }
+
+    /// How many bytes of an index cell payload (both leaf and interior) are stored on the page.
+    pub fn index_local_size(&self, payload_size: u64) -> usize {
+        let max_local = self.fraction_of_usable(self.max_payload_fraction);
+        let min_local = self.fraction_of_usable(self.min_payload_fraction);
+        self.local_size(payload_size, max_local, min_local)
+    }
+
+    /// Amount of payload carried by a single overflow page, the rest is next page pointer.
+    pub fn overflow_payload_size(&self) -> usize {
+        self.usable_size - 4
+    }
+
+    fn fraction_of_usable(&self, fraction: u8) -> usize {
+        (self.usable_size - 12) * fraction as usize / 255 - 23
+    }
+
+    fn local_size(&self, payload_size: u64, max_local: usize, min_local: usize) -> usize {
+        if payload_size <= max_local as u64 {
+            return payload_size as usize;
+        }
+
+        let surplus =
+            min_local as u64 + (payload_size - min_local as u64) % (self.usable_size as u64 - 4);
+        if surplus <= max_local as u64 {
+            surplus as usize
+        } else {
+            min_local
+        }
+    }
+}
+
+impl From<&DbHeader> for PageLayout {
+    fn from(header: &DbHeader) -> Self {
+        PageLayout {
+            usable_size: header.usable_size(),
+            max_payload_fraction: header.max_payload_fraction,
+            min_payload_fraction: header.min_payload_fraction,
+            leaf_payload_fraction: header.leaf_payload_fraction,
+        }
+    }
+}
+
 impl PageSize {
     pub fn real_size(&self) -> usize {
         match self.0 {
@@ -39,7 +157,7 @@ impl PageSize {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum TextEncoding {
     Utf8,
     Utf16Le,
@@ -64,8 +182,9 @@ impl TryFrom<u32> for TextEncoding {
 pub enum Page<'a> {
     InteriorIndex(InteriorIndexPage<'a>),
     LeafIndex(LeafIndexPage<'a>),
-    InteriorTable(InteriorTablePage),
+    InteriorTable(InteriorTablePage<'a>),
     LeafTable(LeafTablePage<'a>),
+    PointerMap(PointerMapPage),
 }
 
 pub struct InteriorPageHeader {
@@ -80,31 +199,54 @@ pub struct InteriorIndexPage<'a> {
     pub header: InteriorPageHeader,
     pub cell_pointers: Vec<u16>,
     pub cells: Vec<InteriorIndexCell<'a>>,
+    /// Reserved space at the end of the page
+    pub reserved: &'a [u8],
 }
 
-pub struct InteriorTablePage {
+pub struct InteriorTablePage<'a> {
     pub header: InteriorPageHeader,
     pub cell_pointers: Vec<u16>,
     pub cells: Vec<InteriorTableCell>,
+    /// Reserved space at the end of the page
+    pub reserved: &'a [u8],
 }
 
+/// Column values of a table row in declared order, `None` being NULL.
+pub type RowValues<'a> = Vec<Option<Payload<'a>>>;
+
+/// Record of an index entry.
+/// When the cell spills onto overflow pages and was not resolved by the `Reader`,
+/// `column_values` contains only the columns stored on the page entirely.
 pub struct IndexCellPayload<'a> {
     pub header_size: u64,
     pub column_types: Vec<SerialType>,
     pub column_values: Vec<Option<Payload<'a>>>,
-    pub rowid: u64,
+}
+
+impl IndexCellPayload<'_> {
+    /// Indexes on rowid tables keep the rowid as the last column of the record.
+    /// `None` if it's not an integer or not available locally.
+    pub fn rowid(&self) -> Option<i64> {
+        match self.column_values.last() {
+            _ if self.column_values.len() != self.column_types.len() => None,
+            Some(Some(v)) => v.as_i64(),
+            _ => None,
+        }
+    }
 }
 
 pub struct InteriorIndexCell<'a> {
     pub left_child_page_no: u32,
     pub payload_size: u64,
     pub payload: IndexCellPayload<'a>,
+    /// Part of the payload stored on the page itself
+    pub local_payload: &'a [u8],
     pub overflow_page_no: Option<u32>,
 }
 
 pub struct InteriorTableCell {
     pub left_child_page_no: u32,
-    pub integer_key: u64,
+    pub integer_key: i64,
 }
 
 pub struct CellOffset(pub u16);
@@ -129,11 +271,15 @@ pub struct LeafIndexPage<'a> {
     pub header: LeafPageHeader,
     pub cell_pointers: Vec<u16>,
     pub cells: Vec<LeafIndexCell<'a>>,
+    /// Reserved space at the end of the page
+    pub reserved: &'a [u8],
 }
 
 pub struct LeafIndexCell<'a> {
     pub payload_size: u64,
     pub payload: IndexCellPayload<'a>,
+    /// Part of the payload stored on the page itself
+    pub local_payload: &'a [u8],
     pub overflow_page_no: Option<u32>,
 }
 
@@ -141,8 +287,13 @@ pub struct LeafTablePage<'a> {
     pub header: LeafPageHeader,
     pub cell_pointers: Vec<u16>,
     pub cells: Vec<LeafTableCell<'a>>,
+    /// Reserved space at the end of the page
+    pub reserved: &'a [u8],
 }
 
+/// Record of a table row.
+/// When the cell spills onto overflow pages and was not resolved by the `Reader`,
+/// `column_values` contains only the columns stored on the page entirely.
 pub struct TableCellPayload<'a> {
     pub header_size: u64,
     pub column_types: Vec<SerialType>,
@@ -151,12 +302,72 @@ pub struct TableCellPayload<'a> {
 
 pub struct LeafTableCell<'a> {
     pub payload_size: u64,
-    pub rowid: u64,
+    pub rowid: i64,
     pub payload: TableCellPayload<'a>,
+    /// Part of the payload stored on the page itself
+    pub local_payload: &'a [u8],
     pub overflow_page_no: Option<u32>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Freelist trunk page, lists free leaf pages and points to the next trunk page.
+/// Freelist leaf pages carry no information and are not parsed.
+pub struct FreelistTrunkPage {
+    pub next_trunk_page_no: Option<u32>,
+    pub no_leaf_pages: u32,
+    pub leaf_page_nos: Vec<u32>,
+}
+
+/// Whole freelist, as collected by walking all the trunk pages.
+#[derive(Debug, Default)]
+pub struct Freelist {
+    pub trunk_page_nos: Vec<u32>,
+    pub leaf_page_nos: Vec<u32>,
+}
+
+impl Freelist {
+    /// Numbers of all free pages, both trunk and leaf.
+    pub fn pages(&self) -> BTreeSet<u32> {
+        self.trunk_page_nos
+            .iter()
+            .chain(self.leaf_page_nos.iter())
+            .copied()
+            .collect()
+    }
+}
+
+/// Size of a pointer-map entry: the page type byte and the parent page number.
+pub const POINTER_MAP_ENTRY_SIZE: usize = 5;
+
+/// Pointer-map page of an auto-vacuumed database.
+/// Contains entries for the pages following it, in order, up to the next pointer-map page.
+/// Slots of pages past the end of the database and of the lock-byte page are `None`.
+pub struct PointerMapPage {
+    pub entries: Vec<Option<PointerMapEntry>>,
+}
+
+/// Tells what the page is used for and which page points to it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PointerMapEntry {
+    /// B-tree root page
+    RootPage,
+    /// Page on the freelist
+    FreePage,
+    /// First page of an overflow chain, parent is the b-tree page holding the cell
+    Overflow1 { parent_page_no: u32 },
+    /// Subsequent page of an overflow chain, parent is the previous overflow page
+    Overflow2 { parent_page_no: u32 },
+    /// Non-root b-tree page, parent is the b-tree page pointing to it
+    BTree { parent_page_no: u32 },
+}
+
+/// Page holding the part of a payload which didn't fit into the b-tree page.
+/// The first overflow page number is stored in the cell, the rest are chained.
+pub struct OverflowPage<'a> {
+    pub next_page_no: Option<u32>,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SerialType {
     Null,
     I8,
@@ -208,33 +419,77 @@ impl SerialType {
             SerialType::F64 => 8,
             SerialType::Const0 => 0,
             SerialType::Const1 => 0,
-            SerialType::Reserved => unimplemented!("reserved"),
+            SerialType::Reserved => 0,
             SerialType::Blob(n) => ((n - 12) / 2).try_into().unwrap(),
             SerialType::Text(n) => ((n - 13) / 2).try_into().unwrap(),
         }
     }
 }
 
+/// Text bytes in the database encoding.
+/// Borrowed from the page, or owned when the payload was assembled from overflow pages.
 #[derive(Debug, Clone, PartialEq)]
-pub struct RawText<'a>(&'a [u8]);
+pub struct RawText<'a>(Cow<'a, [u8]>);
 
 impl<'a> RawText<'a> {
     pub fn new(v: &'a [u8]) -> Self {
-        RawText(v)
+        RawText(Cow::Borrowed(v))
+    }
+
+    pub fn into_owned(self) -> RawText<'static> {
+        RawText(Cow::Owned(self.0.into_owned()))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
     }
 
+    /// Decodes the text replacing invalid sequences with `U+FFFD`.
     pub fn decode(&self, text_encoding: TextEncoding) -> String {
         match text_encoding {
-            TextEncoding::Utf8 => String::from_utf8_lossy(self.0).to_string(),
-            TextEncoding::Utf16Le => unimplemented!("utf16 not supported yet"),
-            TextEncoding::Utf16Be => unimplemented!("utf16 not supported yet"),
+            TextEncoding::Utf8 => String::from_utf8_lossy(&self.0).to_string(),
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                char::decode_utf16(self.utf16_units(text_encoding))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .chain(Some(char::REPLACEMENT_CHARACTER).filter(|_| self.has_dangling_byte()))
+                    .collect()
+            }
         }
     }
+
+    /// Decodes the text failing on the first invalid sequence.
+    pub fn decode_strict(&self, text_encoding: TextEncoding) -> Result<String, SQLiteError> {
+        let invalid = || SQLiteError::TextDecodingError(text_encoding);
+
+        match text_encoding {
+            TextEncoding::Utf8 => String::from_utf8(self.0.to_vec()).map_err(|_| invalid()),
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be if self.has_dangling_byte() => {
+                Err(invalid())
+            }
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                char::decode_utf16(self.utf16_units(text_encoding))
+                    .collect::<Result<String, _>>()
+                    .map_err(|_| invalid())
+            }
+        }
+    }
+
+    fn has_dangling_byte(&self) -> bool {
+        !self.0.chunks_exact(2).remainder().is_empty()
+    }
+
+    /// Code units of UTF-16 text, dangling odd byte is ignored
+    fn utf16_units(&self, text_encoding: TextEncoding) -> impl Iterator<Item = u16> + '_ {
+        self.0.chunks_exact(2).map(move |unit| match text_encoding {
+            TextEncoding::Utf16Be => u16::from_be_bytes([unit[0], unit[1]]),
+            _ => u16::from_le_bytes([unit[0], unit[1]]),
+        })
+    }
 }
 
 impl<'a> From<&'a str> for RawText<'a> {
     fn from(value: &'a str) -> Self {
-        RawText(value.as_bytes())
+        RawText(Cow::Borrowed(value.as_bytes()))
     }
 }
 
@@ -245,10 +500,36 @@ pub enum Payload<'a> {
     I32(i32),
     I64(i64),
     F64(f64),
-    Blob(&'a [u8]),
+    Blob(Cow<'a, [u8]>),
     Text(RawText<'a>),
 }
 
+impl<'a> Payload<'a> {
+    /// Integer value of any width, `None` for other types.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Payload::I8(v) => Some(i64::from(*v)),
+            Payload::I16(v) => Some(i64::from(*v)),
+            Payload::I32(v) => Some(i64::from(*v)),
+            Payload::I64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Detaches the value from the page it was read from.
+    pub fn into_owned(self) -> Payload<'static> {
+        match self {
+            Payload::I8(v) => Payload::I8(v),
+            Payload::I16(v) => Payload::I16(v),
+            Payload::I32(v) => Payload::I32(v),
+            Payload::I64(v) => Payload::I64(v),
+            Payload::F64(v) => Payload::F64(v),
+            Payload::Blob(v) => Payload::Blob(Cow::Owned(v.into_owned())),
+            Payload::Text(v) => Payload::Text(v.into_owned()),
+        }
+    }
+}
+
 impl<'a> From<&'a str> for Payload<'a> {
     fn from(value: &'a str) -> Self {
         Payload::Text(value.into())
@@ -257,7 +538,7 @@ impl<'a> From<&'a str> for Payload<'a> {
 
 impl<'a> From<&'a [u8]> for Payload<'a> {
     fn from(value: &'a [u8]) -> Self {
-        Payload::Blob(value)
+        Payload::Blob(Cow::Borrowed(value))
     }
 }
 
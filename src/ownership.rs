@@ -0,0 +1,234 @@
+//! Map of what every page of the database is used for.
+//!
+//! Pages are claimed by walking every b-tree from its root together with the overflow chains
+//! of its cells, then the freelist. A page reached a second time keeps its first owner,
+//! `Reader::integrity_check` tells about such pages. Pages nothing points to are orphaned.
+
+use std::collections::HashMap;
+
+use crate::chain::{FreelistTrunks, OverflowChain};
+use crate::error::SQLiteError;
+use crate::model::Page;
+use crate::{to_pageno, Reader};
+
+/// Name of the table describing the schema, its b-tree is rooted at page 1
+pub const SCHEMA_TABLE_NAME: &str = "sqlite_schema";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BTreePageKind {
+    InteriorTable,
+    LeafTable,
+    InteriorIndex,
+    LeafIndex,
+}
+
+/// B-trees are told apart by their root page, `PageMap::name` gives the table or index name.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageOwner {
+    /// Page of the table or index b-tree, `kind` is `None` if the page can't be parsed
+    BTree {
+        root_page_no: u32,
+        kind: Option<BTreePageKind>,
+    },
+    /// Page of an overflow chain of a cell in the table or index b-tree
+    Overflow {
+        root_page_no: u32,
+    },
+    FreelistTrunk,
+    FreelistLeaf,
+    PointerMap,
+    /// Page holding the 1GiB offset, which SQLite never uses
+    LockByte,
+    /// Nothing points to the page, it's leaked
+    Orphaned,
+}
+
+/// Owners of all the pages of the database, by page number
+#[derive(Debug, Default)]
+pub struct PageMap {
+    owners: Vec<PageOwner>,
+    /// Table or index names by the root page of their b-tree
+    names: HashMap<u32, String>,
+}
+
+impl PageMap {
+    /// Owner of the page, `page_no` is as stored in the database (1-based).
+    pub fn get(&self, page_no: u32) -> Option<&PageOwner> {
+        self.owners.get((page_no as usize).checked_sub(1)?)
+    }
+
+    /// Name of the table or index whose b-tree is rooted at the page.
+    pub fn name(&self, root_page_no: u32) -> Option<&str> {
+        self.names.get(&root_page_no).map(String::as_str)
+    }
+
+    /// Page numbers with their owners, in the file order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &PageOwner)> {
+        (1..).zip(self.owners.iter())
+    }
+
+    /// Pages which belong to the b-tree rooted at the page, including its overflow pages.
+    pub fn pages_of(&self, root_page_no: u32) -> impl Iterator<Item = u32> + '_ {
+        self.iter()
+            .filter(move |(_, owner)| match owner {
+                PageOwner::BTree {
+                    root_page_no: r, ..
+                }
+                | PageOwner::Overflow {
+                    root_page_no: r, ..
+                } => *r == root_page_no,
+                _ => false,
+            })
+            .map(|(page_no, _)| page_no)
+    }
+
+    pub fn orphaned(&self) -> impl Iterator<Item = u32> + '_ {
+        self.iter()
+            .filter(|(_, owner)| **owner == PageOwner::Orphaned)
+            .map(|(page_no, _)| page_no)
+    }
+}
+
+pub(crate) struct PageMapBuilder<'a, S: AsRef<[u8]>> {
+    reader: &'a Reader<S>,
+    /// Owner of every page, indexed by the page number minus one, `None` until claimed
+    owners: Vec<Option<PageOwner>>,
+    names: HashMap<u32, String>,
+}
+
+impl<'a, S: AsRef<[u8]>> PageMapBuilder<'a, S> {
+    pub(crate) fn new(reader: &'a Reader<S>) -> Self {
+        PageMapBuilder {
+            reader,
+            owners: vec![None; reader.page_count() as usize],
+            names: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn build(mut self) -> Result<PageMap, SQLiteError> {
+        let reader = self.reader;
+        let header = &reader.header;
+        for page_no in reader.ptrmap_page_nos() {
+            self.claim(page_no, PageOwner::PointerMap);
+        }
+        self.claim(header.lock_byte_page_no(), PageOwner::LockByte);
+
+        self.names.insert(1, SCHEMA_TABLE_NAME.to_owned());
+        self.claim_btree(1);
+        for entry in reader.schema()?.entries {
+            if let Some(root_page_no) = entry.root_page.filter(|&p| p != 0) {
+                self.names.insert(root_page_no, entry.name);
+                self.claim_btree(root_page_no);
+            }
+        }
+        self.claim_freelist();
+
+        Ok(PageMap {
+            owners: self
+                .owners
+                .into_iter()
+                .map(|owner| owner.unwrap_or(PageOwner::Orphaned))
+                .collect(),
+            names: self.names,
+        })
+    }
+
+    /// `false` if the page doesn't exist or already has an owner.
+    fn claim(&mut self, page_no: u32, owner: PageOwner) -> bool {
+        match (page_no as usize)
+            .checked_sub(1)
+            .and_then(|i| self.owners.get_mut(i))
+        {
+            Some(slot @ None) => {
+                *slot = Some(owner);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn claim_btree(&mut self, root_page_no: u32) {
+        let mut pages = vec![root_page_no];
+
+        while let Some(page_no) = pages.pop() {
+            let owner = PageOwner::BTree {
+                root_page_no,
+                kind: None,
+            };
+            if !self.claim(page_no, owner) {
+                continue;
+            }
+
+            let page = match to_pageno(page_no).and_then(|p| self.reader.get_local_page(p)) {
+                Ok(page) => page,
+                Err(_) => continue,
+            };
+            let (kind, children, overflow_page_nos): (_, Vec<u32>, Vec<u32>) = match page {
+                Page::InteriorTable(p) => (
+                    BTreePageKind::InteriorTable,
+                    p.cells
+                        .iter()
+                        .map(|c| c.left_child_page_no)
+                        .chain(Some(p.header.rightmost_pointer))
+                        .collect(),
+                    vec![],
+                ),
+                Page::LeafTable(p) => (
+                    BTreePageKind::LeafTable,
+                    vec![],
+                    p.cells.iter().filter_map(|c| c.overflow_page_no).collect(),
+                ),
+                Page::InteriorIndex(p) => (
+                    BTreePageKind::InteriorIndex,
+                    p.cells
+                        .iter()
+                        .map(|c| c.left_child_page_no)
+                        .chain(Some(p.header.rightmost_pointer))
+                        .collect(),
+                    p.cells.iter().filter_map(|c| c.overflow_page_no).collect(),
+                ),
+                Page::LeafIndex(p) => (
+                    BTreePageKind::LeafIndex,
+                    vec![],
+                    p.cells.iter().filter_map(|c| c.overflow_page_no).collect(),
+                ),
+                Page::PointerMap(_) => continue,
+            };
+
+            if let Some(Some(PageOwner::BTree { kind: k, .. })) =
+                self.owners.get_mut(page_no as usize - 1)
+            {
+                *k = Some(kind);
+            }
+            for overflow_page_no in overflow_page_nos {
+                self.claim_overflow_chain(overflow_page_no, root_page_no);
+            }
+            // reversed, so pages are claimed in the key order
+            pages.extend(children.into_iter().rev());
+        }
+    }
+
+    fn claim_overflow_chain(&mut self, first_page_no: u32, root_page_no: u32) {
+        for page_no in OverflowChain::new(self.reader, first_page_no) {
+            if !self.claim(page_no, PageOwner::Overflow { root_page_no }) {
+                return;
+            }
+        }
+    }
+
+    fn claim_freelist(&mut self) {
+        for (trunk_page_no, trunk) in FreelistTrunks::new(self.reader) {
+            if !self.claim(trunk_page_no, PageOwner::FreelistTrunk) {
+                return;
+            }
+            let trunk = match trunk {
+                Ok(trunk) => trunk,
+                Err(_) => return,
+            };
+
+            for leaf_page_no in trunk.leaf_page_nos {
+                self.claim(leaf_page_no, PageOwner::FreelistLeaf);
+            }
+        }
+    }
+}
@@ -3,26 +3,31 @@ use be_i48::be_i48;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::bytes::complete::take;
-use nom::combinator::{complete, map, map_parser, map_res};
-use nom::multi::{count, many0};
+use nom::combinator::{complete, cond, fail, map, map_opt, map_parser, map_res, rest};
+use nom::multi::{count, many0, many_m_n};
 use nom::number::complete::{be_f64, be_i16, be_i24, be_i32, be_i64, be_i8, be_u16, be_u32, be_u8};
 use nom::sequence::{pair, Tuple};
 use nom::IResult;
 
 use crate::model::*;
-use crate::varint::be_u64_varint;
+use crate::varint::{be_i64_varint, be_u64_varint};
 
-const HEADER_SIZE: usize = 100;
+/// Size of the database header at the start of the first page
+pub const HEADER_SIZE: usize = 100;
 
 /// Goes through the whole input page-by-page
 /// NOTE: you should use specific parsers or Reader to parse file lazily
-pub fn database(i: &[u8]) -> IResult<&[u8], Database> {
+pub fn database(i: &[u8]) -> IResult<&[u8], Database<'_>> {
     let (i, header) = db_header(i)?;
 
     let page_size = header.page_size.real_size();
+    let layout = PageLayout::from(&header);
 
-    let root_page = map_parser(take(page_size - HEADER_SIZE), page_generic(HEADER_SIZE));
-    let pages = complete(many0(map_parser(take(page_size), page_generic(0))));
+    let root_page = map_parser(
+        take(page_size - HEADER_SIZE),
+        page_generic(HEADER_SIZE, layout),
+    );
+    let pages = complete(many0(map_parser(take(page_size), page_generic(0, layout))));
 
     let (i, (root_page, mut pages)) = complete(pair(root_page, pages))(i)?;
 
@@ -36,7 +41,7 @@ pub fn db_header(i: &[u8]) -> IResult<&[u8], DbHeader> {
     let (i, _) = tag("SQLite format 3\0")(i)?;
     let (i, page_size) = map(be_u16, PageSize)(i)?;
     let (i, (write_version, read_version)) = (be_u8, be_u8).parse(i)?;
-    let (i, _reserved) = be_u8(i)?;
+    let (i, reserved_bytes) = be_u8(i)?;
     let (i, (max_payload_fraction, min_payload_fraction, leaf_payload_fraction)) =
         (be_u8, be_u8, be_u8).parse(i)?;
     let (i, file_change_counter) = be_u32(i)?;
@@ -58,6 +63,7 @@ pub fn db_header(i: &[u8]) -> IResult<&[u8], DbHeader> {
             page_size,
             write_version,
             read_version,
+            reserved_bytes,
             max_payload_fraction,
             min_payload_fraction,
             leaf_payload_fraction,
@@ -80,34 +86,124 @@ pub fn db_header(i: &[u8]) -> IResult<&[u8], DbHeader> {
 }
 
 /// The page number 0, which comes right after the header. Input assumed to contain the header.
-pub fn root_page(i: &[u8]) -> IResult<&[u8], Page> {
-    let shrunk_page = &i[HEADER_SIZE..];
-    page_generic(HEADER_SIZE)(shrunk_page)
+/// Usable page size is assumed to be the size of the input, so no reserved space.
+pub fn root_page(i: &[u8]) -> IResult<&[u8], Page<'_>> {
+    root_page_with_layout(PageLayout::new(i.len()))(i)
 }
 
 /// All the rest of pages, pageno >0.
-pub fn page(i: &[u8]) -> IResult<&[u8], Page> {
-    page_generic(0)(i)
+/// Usable page size is assumed to be the size of the input, so no reserved space.
+pub fn page(i: &[u8]) -> IResult<&[u8], Page<'_>> {
+    page_with_layout(PageLayout::new(i.len()))(i)
+}
+
+/// Same as `root_page`, but with page layout taken from the file header.
+pub fn root_page_with_layout(layout: PageLayout) -> impl FnMut(&[u8]) -> IResult<&[u8], Page<'_>> {
+    move |i| {
+        let shrunk_page = &i[HEADER_SIZE..];
+        page_generic(HEADER_SIZE, layout)(shrunk_page)
+    }
+}
+
+/// Same as `page`, but with page layout taken from the file header.
+pub fn page_with_layout(layout: PageLayout) -> impl FnMut(&[u8]) -> IResult<&[u8], Page<'_>> {
+    move |i| page_generic(0, layout)(i)
+}
+
+/// Overflow page, the input is expected to be exactly the usable part of the page.
+pub fn overflow_page(i: &[u8]) -> IResult<&[u8], OverflowPage<'_>> {
+    let (i, next_page_no) = map(be_u32, |u| Some(u).filter(|&p| p != 0x0u32))(i)?;
+    let (i, payload) = rest(i)?;
+
+    Ok((
+        i,
+        OverflowPage {
+            next_page_no,
+            payload,
+        },
+    ))
+}
+
+/// Freelist trunk page, the input is expected to be exactly the usable part of the page.
+pub fn freelist_trunk_page(i: &[u8]) -> IResult<&[u8], FreelistTrunkPage> {
+    let (i, next_trunk_page_no) = map(be_u32, |u| Some(u).filter(|&p| p != 0x0u32))(i)?;
+    let (i, no_leaf_pages) = be_u32(i)?;
+    let (i, leaf_page_nos) = count(be_u32, no_leaf_pages as usize)(i)?;
+
+    Ok((
+        i,
+        FreelistTrunkPage {
+            next_trunk_page_no,
+            no_leaf_pages,
+            leaf_page_nos,
+        },
+    ))
+}
+
+/// Pointer-map page, the input is expected to be exactly the usable part of the page.
+/// Every 5-byte slot is parsed, unused ones (such as the lock-byte page's) are `None`.
+pub fn pointer_map_page(i: &[u8]) -> IResult<&[u8], PointerMapPage> {
+    let (i, entries) = count(pointer_map_entry, i.len() / POINTER_MAP_ENTRY_SIZE)(i)?;
+
+    Ok((i, PointerMapPage { entries }))
+}
+
+fn pointer_map_entry(i: &[u8]) -> IResult<&[u8], Option<PointerMapEntry>> {
+    map_opt(pair(be_u8, be_u32), |(kind, parent_page_no)| match kind {
+        0 => Some(None),
+        1 => Some(Some(PointerMapEntry::RootPage)),
+        2 => Some(Some(PointerMapEntry::FreePage)),
+        3 => Some(Some(PointerMapEntry::Overflow1 { parent_page_no })),
+        4 => Some(Some(PointerMapEntry::Overflow2 { parent_page_no })),
+        5 => Some(Some(PointerMapEntry::BTree { parent_page_no })),
+        _ => None,
+    })(i)
 }
 
 // todo: fix const generic thing, hack to pass through parameters
-fn page_generic(page_start_offset: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], Page> {
+fn page_generic(
+    page_start_offset: usize,
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], Page<'_>> {
     move |i| {
         alt((
             map(
-                interior_index_b_tree_page(page_start_offset),
+                interior_index_b_tree_page(page_start_offset, layout),
                 Page::InteriorIndex,
             ),
-            map(leaf_index_b_tree_page(page_start_offset), Page::LeafIndex),
             map(
-                interior_table_b_tree_page(page_start_offset),
+                leaf_index_b_tree_page(page_start_offset, layout),
+                Page::LeafIndex,
+            ),
+            map(
+                interior_table_b_tree_page(page_start_offset, layout),
                 Page::InteriorTable,
             ),
-            map(leaf_table_b_tree_page(page_start_offset), Page::LeafTable),
+            map(
+                leaf_table_b_tree_page(page_start_offset, layout),
+                Page::LeafTable,
+            ),
         ))(i)
     }
 }
 
+/// Splits the page into the usable area, which holds the cells, and the reserved tail.
+fn split_reserved(i: &[u8], page_start_offset: usize, layout: PageLayout) -> (&[u8], &[u8]) {
+    let usable_len = layout.usable_size.saturating_sub(page_start_offset);
+    i.split_at(usable_len.min(i.len()))
+}
+
+/// Cell content at the pointer, which is relative to the page start rather than the input.
+fn cell_at(i: &[u8], ptr: u16, page_start_offset: usize) -> IResult<&[u8], &[u8]> {
+    match (ptr as usize)
+        .checked_sub(page_start_offset)
+        .and_then(|offset| i.get(offset..))
+    {
+        Some(cell_bytes) => Ok((i, cell_bytes)),
+        None => fail(i),
+    }
+}
+
 fn interior_page_header(i: &[u8]) -> IResult<&[u8], InteriorPageHeader> {
     let (i, first_freeblock_offset) = map(be_u16, |u| Some(u).filter(|&p| p != 0x0u16))(i)?;
     let (i, no_cells) = be_u16(i)?;
@@ -146,16 +242,18 @@ fn leaf_page_header(i: &[u8]) -> IResult<&[u8], LeafPageHeader> {
 
 fn interior_index_b_tree_page(
     page_start_offset: usize,
-) -> impl FnMut(&[u8]) -> IResult<&[u8], InteriorIndexPage> {
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], InteriorIndexPage<'_>> {
     move |i| {
+        let (i, reserved) = split_reserved(i, page_start_offset, layout);
         let (ii, _) = tag([0x02u8])(i)?;
         let (ii, header) = interior_page_header(ii)?;
         let (ii, cell_pointers) = count(be_u16, header.no_cells.into())(ii)?;
 
         let mut cells = Vec::with_capacity(cell_pointers.len());
         for &ptr in cell_pointers.iter() {
-            let cell_offset = ptr as usize - page_start_offset;
-            let (_, cell) = interior_index_cell(&i[cell_offset..])?;
+            let (_, cell_bytes) = cell_at(i, ptr, page_start_offset)?;
+            let (_, cell) = interior_index_cell(layout)(cell_bytes)?;
             cells.push(cell);
         }
 
@@ -165,6 +263,7 @@ fn interior_index_b_tree_page(
                 header,
                 cell_pointers,
                 cells,
+                reserved,
             },
         ))
     }
@@ -176,40 +275,44 @@ fn column_types(i: &[u8]) -> IResult<&[u8], Vec<SerialType>> {
     complete(many0(map(be_u64_varint, SerialType::from)))(i)
 }
 
-fn text_payload(size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<Payload>> {
+fn text_payload(size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<Payload<'_>>> {
     move |i| map(take(size), |x: &[u8]| Some(Payload::Text(RawText::new(x))))(i)
 }
 
-fn blob_payload(size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<Payload>> {
-    move |i| map(take(size), |x: &[u8]| Some(Payload::Blob(x)))(i)
+fn blob_payload(size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<Payload<'_>>> {
+    move |i| map(take(size), |x: &[u8]| Some(Payload::from(x)))(i)
 }
 
-fn column_values<'a, 'b>(
+fn column_value<'a>(
+    serial_type: &SerialType,
+) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Option<Payload<'a>>> + '_ {
+    move |i| match serial_type {
+        SerialType::Null => Ok((i, None)),
+        SerialType::I8 => map(be_i8, |x| Some(Payload::I8(x)))(i),
+        SerialType::I16 => map(be_i16, |x| Some(Payload::I16(x)))(i),
+        SerialType::I24 => map(be_i24, |x| Some(Payload::I32(x)))(i),
+        SerialType::I32 => map(be_i32, |x| Some(Payload::I32(x)))(i),
+        SerialType::I48 => map(be_i48, |x| Some(Payload::I64(x)))(i),
+        SerialType::I64 => map(be_i64, |x| Some(Payload::I64(x)))(i),
+        SerialType::F64 => map(be_f64, |x| Some(Payload::F64(x)))(i),
+        SerialType::Const0 => Ok((i, Some(Payload::I8(0)))),
+        SerialType::Const1 => Ok((i, Some(Payload::I8(1)))),
+        // never written by SQLite, only found in corrupted or carved records
+        SerialType::Reserved => fail(i),
+        SerialType::Blob(_) => blob_payload(serial_type.size())(i),
+        SerialType::Text(_) => text_payload(serial_type.size())(i),
+    }
+}
+
+pub(crate) fn column_values<'a, 'b>(
     serial_types: &'b [SerialType],
-) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<Option<Payload>>> + 'b {
+) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<Option<Payload<'a>>>> + 'b {
     move |i| {
         let mut i: &[u8] = i;
         let mut res = Vec::with_capacity(serial_types.len());
         for serial_type in serial_types {
-            let (ii, v) = match serial_type {
-                SerialType::Null => Ok((i, None)),
-                SerialType::I8 => map(be_i8, |x| Some(Payload::I8(x)))(i),
-                SerialType::I16 => map(be_i16, |x| Some(Payload::I16(x)))(i),
-                SerialType::I24 => map(be_i24, |x| Some(Payload::I32(x)))(i),
-                SerialType::I32 => map(be_i32, |x| Some(Payload::I32(x)))(i),
-                SerialType::I48 => map(be_i48, |x| Some(Payload::I64(x)))(i),
-                SerialType::I64 => map(be_i64, |x| Some(Payload::I64(x)))(i),
-                SerialType::F64 => map(be_f64, |x| Some(Payload::F64(x)))(i),
-                SerialType::Const0 => Ok((i, Some(Payload::I8(0)))),
-                SerialType::Const1 => Ok((i, Some(Payload::I8(0)))),
-                SerialType::Reserved => unimplemented!("reserved"),
-                SerialType::Blob(_) if serial_type.size() == 0 => Ok((i, None)),
-                SerialType::Blob(_) => blob_payload(serial_type.size())(i),
-                SerialType::Text(_) if serial_type.size() == 0 => Ok((i, None)),
-                SerialType::Text(_) => text_payload(serial_type.size())(i),
-            }?;
+            let (ii, v) = column_value(serial_type)(i)?;
             i = ii;
-            dbg!(v.clone());
             res.push(v);
         }
 
@@ -217,51 +320,117 @@ fn column_values<'a, 'b>(
     }
 }
 
-fn index_cell_payload(i: &[u8]) -> IResult<&[u8], IndexCellPayload> {
-    let (i, header_size) = be_u64_varint(i)?;
-    let (_, column_types) = column_types(&i[0..header_size as usize - 1])?;
-    let (i, column_values) = column_values(&column_types)(&i[header_size as usize - 1..])?;
-    let (i, rowid) = be_u64_varint(i)?;
+/// Record header: its size (including the size varint itself) and column serial types.
+fn record_header(i: &[u8]) -> IResult<&[u8], (u64, Vec<SerialType>)> {
+    let (ii, header_size) = be_u64_varint(i)?;
+    let size_len = i.len() - ii.len();
+    let (ii, types) = take((header_size as usize).saturating_sub(size_len))(ii)?;
+    let (_, column_types) = column_types(types)?;
 
-    Ok((
-        i,
-        IndexCellPayload {
-            header_size,
-            column_types,
-            column_values,
-            rowid,
-        },
-    ))
+    Ok((ii, (header_size, column_types)))
 }
 
-fn interior_index_cell(i: &[u8]) -> IResult<&[u8], InteriorIndexCell> {
-    let (i, left_child_page_no) = be_u32(i)?;
-    let (i, payload_size) = be_u64_varint(i)?;
-    let (i, payload) = index_cell_payload(i)?;
+type Record<'a> = (u64, Vec<SerialType>, Vec<Option<Payload<'a>>>);
 
-    Ok((
-        i,
-        InteriorIndexCell {
-            left_child_page_no,
-            payload_size,
-            payload,
-            overflow_page_no: None,
-        },
-    ))
+fn record(i: &[u8]) -> IResult<&[u8], Record<'_>> {
+    let (i, (header_size, column_types)) = record_header(i)?;
+    let (i, column_values) = column_values(&column_types)(i)?;
+
+    Ok((i, (header_size, column_types, column_values)))
+}
+
+/// Decodes as much as possible from the local part of an overflowing payload:
+/// the serial types which fit and the values which are stored on the page entirely.
+fn partial_record(i: &[u8]) -> IResult<&[u8], Record<'_>> {
+    let (ii, header_size) = be_u64_varint(i)?;
+    let size_len = i.len() - ii.len();
+    let types_len = (header_size as usize)
+        .saturating_sub(size_len)
+        .min(ii.len());
+    let (mut ii, types) = take(types_len)(ii)?;
+    let (_, column_types) = column_types(types)?;
+
+    let mut column_values = Vec::with_capacity(column_types.len());
+    if types_len + size_len == header_size as usize {
+        for serial_type in column_types.iter() {
+            match column_value(serial_type)(ii) {
+                Ok((rest, v)) => {
+                    ii = rest;
+                    column_values.push(v);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    Ok((ii, (header_size, column_types, column_values)))
+}
+
+fn overflow_page_no(
+    payload_size: u64,
+    local_size: usize,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], Option<u32>> {
+    move |i| cond(payload_size > local_size as u64, be_u32)(i)
+}
+
+fn index_payload(record: Record) -> IndexCellPayload {
+    let (header_size, column_types, column_values) = record;
+
+    IndexCellPayload {
+        header_size,
+        column_types,
+        column_values,
+    }
+}
+
+/// Complete index record, either stored on the page entirely or assembled from overflow pages.
+/// Expects to get exactly the payload bytes.
+pub fn index_cell_payload(i: &[u8]) -> IResult<&[u8], IndexCellPayload<'_>> {
+    map(record, index_payload)(i)
+}
+
+fn interior_index_cell(
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], InteriorIndexCell<'_>> {
+    move |i| {
+        let (i, left_child_page_no) = be_u32(i)?;
+        let (i, payload_size) = be_u64_varint(i)?;
+        let local_size = layout.index_local_size(payload_size);
+        let (i, local_payload) = take(local_size)(i)?;
+        let (i, overflow_page_no) = overflow_page_no(payload_size, local_size)(i)?;
+        let (_, payload) = if overflow_page_no.is_some() {
+            map(partial_record, index_payload)(local_payload)
+        } else {
+            index_cell_payload(local_payload)
+        }?;
+
+        Ok((
+            i,
+            InteriorIndexCell {
+                left_child_page_no,
+                payload_size,
+                payload,
+                local_payload,
+                overflow_page_no,
+            },
+        ))
+    }
 }
 
 fn interior_table_b_tree_page(
     page_start_offset: usize,
-) -> impl FnMut(&[u8]) -> IResult<&[u8], InteriorTablePage> {
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], InteriorTablePage<'_>> {
     move |i| {
+        let (i, reserved) = split_reserved(i, page_start_offset, layout);
         let (ii, _) = tag([0x05u8])(i)?;
         let (ii, header) = interior_page_header(ii)?;
         let (ii, cell_pointers) = count(be_u16, header.no_cells.into())(ii)?;
 
         let mut cells = Vec::with_capacity(cell_pointers.len());
         for &ptr in cell_pointers.iter() {
-            let cell_offset = ptr as usize - page_start_offset;
-            let (_, cell) = interior_table_cell(&i[cell_offset..])?;
+            let (_, cell_bytes) = cell_at(i, ptr, page_start_offset)?;
+            let (_, cell) = interior_table_cell(cell_bytes)?;
             cells.push(cell);
         }
 
@@ -271,6 +440,7 @@ fn interior_table_b_tree_page(
                 header,
                 cell_pointers,
                 cells,
+                reserved,
             },
         ))
     }
@@ -278,7 +448,7 @@ fn interior_table_b_tree_page(
 
 fn interior_table_cell(i: &[u8]) -> IResult<&[u8], InteriorTableCell> {
     let (i, left_child_page_no) = be_u32(i)?;
-    let (i, integer_key) = be_u64_varint(i)?;
+    let (i, integer_key) = be_i64_varint(i)?;
 
     Ok((
         i,
@@ -291,16 +461,18 @@ fn interior_table_cell(i: &[u8]) -> IResult<&[u8], InteriorTableCell> {
 
 fn leaf_index_b_tree_page(
     page_start_offset: usize,
-) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafIndexPage> {
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafIndexPage<'_>> {
     move |i| {
+        let (i, reserved) = split_reserved(i, page_start_offset, layout);
         let (ii, _) = tag([0x0au8])(i)?;
         let (ii, header) = leaf_page_header(ii)?;
         let (ii, cell_pointers) = count(be_u16, header.no_cells.into())(ii)?;
 
         let mut cells = Vec::with_capacity(cell_pointers.len());
         for &ptr in cell_pointers.iter() {
-            let cell_offset = ptr as usize - page_start_offset;
-            let (_, cell) = leaf_index_cell(&i[cell_offset..])?;
+            let (_, cell_bytes) = cell_at(i, ptr, page_start_offset)?;
+            let (_, cell) = leaf_index_cell(layout)(cell_bytes)?;
             cells.push(cell);
         }
 
@@ -310,37 +482,50 @@ fn leaf_index_b_tree_page(
                 header,
                 cell_pointers,
                 cells,
+                reserved,
             },
         ))
     }
 }
 
-fn leaf_index_cell(i: &[u8]) -> IResult<&[u8], LeafIndexCell> {
-    let (i, payload_size) = be_u64_varint(i)?;
-    let (i, payload) = index_cell_payload(i)?;
+fn leaf_index_cell(layout: PageLayout) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafIndexCell<'_>> {
+    move |i| {
+        let (i, payload_size) = be_u64_varint(i)?;
+        let local_size = layout.index_local_size(payload_size);
+        let (i, local_payload) = take(local_size)(i)?;
+        let (i, overflow_page_no) = overflow_page_no(payload_size, local_size)(i)?;
+        let (_, payload) = if overflow_page_no.is_some() {
+            map(partial_record, index_payload)(local_payload)
+        } else {
+            index_cell_payload(local_payload)
+        }?;
 
-    Ok((
-        i,
-        LeafIndexCell {
-            payload_size,
-            payload,
-            overflow_page_no: None,
-        },
-    ))
+        Ok((
+            i,
+            LeafIndexCell {
+                payload_size,
+                payload,
+                local_payload,
+                overflow_page_no,
+            },
+        ))
+    }
 }
 
 fn leaf_table_b_tree_page(
     page_start_offset: usize,
-) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafTablePage> {
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafTablePage<'_>> {
     move |i| {
+        let (i, reserved) = split_reserved(i, page_start_offset, layout);
         let (ii, _) = tag([0x0du8])(i)?;
         let (ii, header) = leaf_page_header(ii)?;
         let (ii, cell_pointers) = count(be_u16, header.no_cells.into())(ii)?;
 
         let mut cells = Vec::with_capacity(cell_pointers.len());
         for &ptr in cell_pointers.iter() {
-            let cell_offset = ptr as usize - page_start_offset;
-            let (_, cell) = leaf_table_cell(&i[cell_offset..])?;
+            let (_, cell_bytes) = cell_at(i, ptr, page_start_offset)?;
+            let (_, cell) = leaf_table_cell(layout)(cell_bytes)?;
             cells.push(cell);
         }
 
@@ -350,38 +535,84 @@ fn leaf_table_b_tree_page(
                 header,
                 cell_pointers,
                 cells,
+                reserved,
             },
         ))
     }
 }
 
-fn table_cell_payload(i: &[u8]) -> IResult<&[u8], TableCellPayload> {
-    let (i, header_size) = be_u64_varint(i)?;
-    let (_, column_types) = column_types(&i[0..header_size as usize - 1])?;
-    let (i, column_values) = column_values(&column_types)(&i[header_size as usize - 1..])?;
+/// Same as a leaf table page parsed by `page`, but cells which fail to parse are skipped
+/// instead of failing the whole page, e.g. on a freed page which was partially overwritten.
+/// Only the pointers of the decoded cells are kept, in the same order as the cells.
+pub fn leaf_table_page_lenient(
+    layout: PageLayout,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafTablePage<'_>> {
+    move |i| {
+        let (i, reserved) = split_reserved(i, 0, layout);
+        let (ii, _) = tag([0x0du8])(i)?;
+        let (ii, header) = leaf_page_header(ii)?;
+        // the array itself could be cut short by the end of the page
+        let (ii, cell_pointers) = many_m_n(0, header.no_cells.into(), be_u16)(ii)?;
+
+        let (cell_pointers, cells) = cell_pointers
+            .into_iter()
+            .filter_map(|ptr| {
+                let (_, cell_bytes) = cell_at(i, ptr, 0).ok()?;
+                let (_, cell) = leaf_table_cell(layout)(cell_bytes).ok()?;
+                Some((ptr, cell))
+            })
+            .unzip();
 
-    Ok((
-        i,
-        TableCellPayload {
-            header_size,
-            column_types,
-            column_values,
-        },
-    ))
+        Ok((
+            ii,
+            LeafTablePage {
+                header,
+                cell_pointers,
+                cells,
+                reserved,
+            },
+        ))
+    }
 }
 
-fn leaf_table_cell(i: &[u8]) -> IResult<&[u8], LeafTableCell> {
-    let (i, payload_size) = be_u64_varint(i)?;
-    let (i, rowid) = be_u64_varint(i)?;
-    let (i, payload) = table_cell_payload(i)?;
+fn table_payload(record: Record) -> TableCellPayload {
+    let (header_size, column_types, column_values) = record;
 
-    Ok((
-        i,
-        LeafTableCell {
-            payload_size,
-            rowid,
-            payload,
-            overflow_page_no: None,
-        },
-    ))
+    TableCellPayload {
+        header_size,
+        column_types,
+        column_values,
+    }
+}
+
+/// Complete table record, either stored on the page entirely or assembled from overflow pages.
+/// Expects to get exactly the payload bytes.
+pub fn table_cell_payload(i: &[u8]) -> IResult<&[u8], TableCellPayload<'_>> {
+    map(record, table_payload)(i)
+}
+
+fn leaf_table_cell(layout: PageLayout) -> impl FnMut(&[u8]) -> IResult<&[u8], LeafTableCell<'_>> {
+    move |i| {
+        let (i, payload_size) = be_u64_varint(i)?;
+        let (i, rowid) = be_i64_varint(i)?;
+        let local_size = layout.table_leaf_local_size(payload_size);
+        let (i, local_payload) = take(local_size)(i)?;
+        let (i, overflow_page_no) = overflow_page_no(payload_size, local_size)(i)?;
+        let (_, payload) = if overflow_page_no.is_some() {
+            map(partial_record, table_payload)(local_payload)
+        } else {
+            table_cell_payload(local_payload)
+        }?;
+
+        Ok((
+            i,
+            LeafTableCell {
+                payload_size,
+                rowid,
+                payload,
+                local_payload,
+                overflow_page_no,
+            },
+        ))
+    }
 }
@@ -0,0 +1,221 @@
+use crate::compare::{Collation, KeyColumn};
+use crate::ddl::{
+    parse_create_index, parse_create_table, IndexDefinition, IndexedColumn, TableDefinition,
+};
+use crate::error::SQLiteError;
+use crate::model::{Payload, TableCellPayload, TextEncoding};
+
+/// Type of the sqlite_schema entry
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SchemaEntryKind {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl TryFrom<&str> for SchemaEntryKind {
+    type Error = SQLiteError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        use SchemaEntryKind::*;
+
+        match value {
+            "table" => Ok(Table),
+            "index" => Ok(Index),
+            "view" => Ok(View),
+            "trigger" => Ok(Trigger),
+            _ => Err(SQLiteError::SchemaError(format!(
+                "unknown type `{}`",
+                value
+            ))),
+        }
+    }
+}
+
+/// Row of sqlite_schema table
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaEntry {
+    pub kind: SchemaEntryKind,
+    pub name: String,
+    /// Table the entry belongs to, for tables it's the name itself
+    pub tbl_name: String,
+    /// Root b-tree page (1-based), `None` for views and triggers
+    pub root_page: Option<u32>,
+    /// `None` for indexes created automatically by UNIQUE and PRIMARY KEY constraints
+    pub sql: Option<String>,
+}
+
+impl SchemaEntry {
+    /// Columns and constraints from the CREATE TABLE statement.
+    pub fn table_definition(&self) -> Result<TableDefinition, SQLiteError> {
+        match (self.kind, &self.sql) {
+            (SchemaEntryKind::Table, Some(sql)) => parse_create_table(sql),
+            _ => Err(SQLiteError::SchemaError(format!(
+                "`{}` is not a table",
+                self.name
+            ))),
+        }
+    }
+
+    /// Indexed columns from the CREATE INDEX statement.
+    /// Automatic indexes have no SQL and therefore no definition.
+    pub fn index_definition(&self) -> Result<IndexDefinition, SQLiteError> {
+        match (self.kind, &self.sql) {
+            (SchemaEntryKind::Index, Some(sql)) => parse_create_index(sql),
+            _ => Err(SQLiteError::SchemaError(format!(
+                "`{}` is not an index with definition",
+                self.name
+            ))),
+        }
+    }
+}
+
+/// Contents of sqlite_schema table, describing every table, index, view and trigger.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    pub entries: Vec<SchemaEntry>,
+}
+
+impl Schema {
+    /// Looks up the table by name, case insensitive as SQL identifiers are.
+    pub fn table(&self, name: &str) -> Option<&SchemaEntry> {
+        self.find(SchemaEntryKind::Table, name)
+    }
+
+    pub fn index(&self, name: &str) -> Option<&SchemaEntry> {
+        self.find(SchemaEntryKind::Index, name)
+    }
+
+    pub fn view(&self, name: &str) -> Option<&SchemaEntry> {
+        self.find(SchemaEntryKind::View, name)
+    }
+
+    pub fn trigger(&self, name: &str) -> Option<&SchemaEntry> {
+        self.find(SchemaEntryKind::Trigger, name)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &SchemaEntry> {
+        self.of_kind(SchemaEntryKind::Table)
+    }
+
+    /// All indexes of the table, including the automatic ones.
+    pub fn indexes_of<'a>(&'a self, tbl_name: &'a str) -> impl Iterator<Item = &'a SchemaEntry> {
+        self.of_kind(SchemaEntryKind::Index)
+            .filter(move |e| e.tbl_name.eq_ignore_ascii_case(tbl_name))
+    }
+
+    /// Entry owning the b-tree with given root page.
+    pub fn by_root_page(&self, root_page: u32) -> Option<&SchemaEntry> {
+        self.entries.iter().find(|e| e.root_page == Some(root_page))
+    }
+
+    /// Collations and sort orders of the record columns of the b-tree with given root page,
+    /// in the order entries are sorted by. Indexes of rowid tables end with the rowid,
+    /// which needs no column, indexes of WITHOUT ROWID tables end with the primary key.
+    /// Empty for rowid tables, which are ordered by the rowid alone.
+    ///
+    /// Fails when the collation of an expression can't be told without evaluating it.
+    pub fn key_columns(&self, root_page: u32) -> Result<Vec<KeyColumn>, SQLiteError> {
+        let entry = self
+            .by_root_page(root_page)
+            .ok_or(SQLiteError::KeyColumnsError(root_page))?;
+        let table = self
+            .table(&entry.tbl_name)
+            .ok_or(SQLiteError::KeyColumnsError(root_page))?
+            .table_definition()?;
+        let key_column = |column: &IndexedColumn| -> Result<KeyColumn, SQLiteError> {
+            let collation = match table.collation_of(column) {
+                Some(collation) => Collation::try_from(collation)?,
+                // an expression inherits the collation of a column it consists of
+                None if column.expression.as_ref().is_some_and(|e| {
+                    e.to_ascii_uppercase().contains("COLLATE")
+                        || table.columns.iter().any(|c| c.collation.is_some())
+                }) =>
+                {
+                    return Err(SQLiteError::KeyColumnsError(root_page))
+                }
+                None => Collation::Binary,
+            };
+
+            Ok(KeyColumn {
+                collation,
+                order: column.order,
+            })
+        };
+
+        let key = match entry.kind {
+            SchemaEntryKind::Table if table.without_rowid => table.primary_key.clone(),
+            SchemaEntryKind::Table => return Ok(Vec::new()),
+            SchemaEntryKind::Index => match entry.sql {
+                Some(_) => entry.index_definition()?.columns,
+                None => entry
+                    .name
+                    .strip_prefix("sqlite_autoindex_")
+                    .and_then(|n| n.get(entry.tbl_name.len() + 1..))
+                    .and_then(|n| n.parse().ok())
+                    .and_then(|n| table.automatic_index(n))
+                    .ok_or(SQLiteError::KeyColumnsError(root_page))?
+                    .to_vec(),
+            },
+            _ => return Err(SQLiteError::KeyColumnsError(root_page)),
+        };
+        let mut columns = key.iter().map(key_column).collect::<Result<Vec<_>, _>>()?;
+
+        // primary key columns follow, unless the index has them already with the same collation
+        if entry.kind == SchemaEntryKind::Index && table.without_rowid {
+            for pk_column in table.primary_key.iter() {
+                if !key.iter().any(|c| table.same_key_column(c, pk_column)) {
+                    columns.push(key_column(pk_column)?);
+                }
+            }
+        }
+
+        Ok(columns)
+    }
+
+    fn find(&self, kind: SchemaEntryKind, name: &str) -> Option<&SchemaEntry> {
+        self.of_kind(kind)
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    fn of_kind(&self, kind: SchemaEntryKind) -> impl Iterator<Item = &SchemaEntry> {
+        self.entries.iter().filter(move |e| e.kind == kind)
+    }
+}
+
+/// Decodes sqlite_schema row: type, name, tbl_name, rootpage, sql
+pub(crate) fn schema_entry(
+    payload: &TableCellPayload,
+    text_encoding: TextEncoding,
+) -> Result<SchemaEntry, SQLiteError> {
+    let text = |column: usize| -> Result<Option<String>, SQLiteError> {
+        match payload.column_values.get(column) {
+            Some(Some(Payload::Text(text))) => Ok(Some(text.decode_strict(text_encoding)?)),
+            Some(None) => Ok(None),
+            _ => Err(SQLiteError::SchemaError(format!(
+                "column {} is not text",
+                column
+            ))),
+        }
+    };
+    let required_text = |column: usize| -> Result<String, SQLiteError> {
+        text(column)?.ok_or_else(|| SQLiteError::SchemaError(format!("column {} is null", column)))
+    };
+
+    let kind = SchemaEntryKind::try_from(required_text(0)?.as_str())?;
+    let root_page = match payload.column_values.get(3) {
+        Some(Some(v)) => v.as_i64().and_then(|p| u32::try_from(p).ok()),
+        Some(None) => None,
+        None => None,
+    }
+    .filter(|&p| p != 0);
+
+    Ok(SchemaEntry {
+        kind,
+        name: required_text(1)?,
+        tbl_name: required_text(2)?,
+        root_page,
+        sql: text(4)?,
+    })
+}
@@ -0,0 +1,349 @@
+//! Wal-index, the `-shm` file SQLite keeps next to the write-ahead log.
+//!
+//! It's a cache of the log rebuilt on recovery, so it's only trusted when both copies of its
+//! header agree and match the log. Unlike the log it's written in the native byte order
+//! of the machine which wrote it.
+//!
+//! The file is split into 32KB blocks, each holding page numbers of consecutive frames
+//! followed by a hash table from page number to the position of its frame in the block.
+//! The first block starts with the headers, so it covers fewer frames.
+
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapOptions};
+use nom::branch::alt;
+use nom::bytes::complete::take;
+use nom::combinator::{map, peek, verify};
+use nom::multi::count;
+use nom::number::complete::{be_u32, le_u32, u16, u32, u8};
+use nom::number::Endianness;
+use nom::sequence::Tuple;
+use nom::{Finish, IResult};
+
+use crate::error::SQLiteError;
+use crate::wal::{wal_checksum, WalReader};
+
+pub const WAL_INDEX_VERSION: u32 = 3007000;
+pub const WAL_INDEX_HEADER_SIZE: usize = 48;
+/// Two copies of the header followed by the checkpoint information
+pub const WAL_INDEX_HEADERS_SIZE: usize = 136;
+pub const WAL_INDEX_BLOCK_SIZE: usize = 32768;
+/// Frames covered by a block
+pub const HASH_TABLE_PAGES: usize = 4096;
+/// Frames covered by the first block, the rest of it is taken by the headers
+pub const HASH_TABLE_PAGES_FIRST: usize = HASH_TABLE_PAGES - WAL_INDEX_HEADERS_SIZE / 4;
+pub const HASH_TABLE_SLOTS: usize = HASH_TABLE_PAGES * 2;
+/// Read mark of a reader slot nobody uses
+pub const READ_MARK_NOT_USED: u32 = 0xffffffff;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WalIndexHeader {
+    pub version: u32,
+    /// Incremented on every transaction
+    pub change_counter: u32,
+    pub is_init: bool,
+    pub big_endian_checksums: bool,
+    pub page_size: u32,
+    /// Last valid commit frame in the log, 0 if the log is empty
+    pub max_frame: u32,
+    /// Database size in pages
+    pub db_size: u32,
+    /// Checksum of the `max_frame` frame
+    pub frame_checksum: (u32, u32),
+    /// Same as in the log header
+    pub salt_1: u32,
+    pub salt_2: u32,
+    /// Checksum of this header
+    pub checksum: (u32, u32),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CheckpointInfo {
+    /// Frames up to this one were copied into the database file
+    pub backfill: u32,
+    /// Frame each reader slot reads up to
+    pub read_marks: [u32; 5],
+    pub locks: [u8; 8],
+    /// Frames up to this one were attempted to be copied
+    pub backfill_attempted: u32,
+}
+
+/// Position of a frame in one of the hash table blocks
+fn block_of(frame_no: u32) -> usize {
+    match (frame_no as usize).checked_sub(HASH_TABLE_PAGES_FIRST + 1) {
+        None => 0,
+        Some(rest) => rest / HASH_TABLE_PAGES + 1,
+    }
+}
+
+/// Frame number preceding the first frame of the block
+fn block_base(block: usize) -> u32 {
+    match block {
+        0 => 0,
+        _ => (HASH_TABLE_PAGES_FIRST + (block - 1) * HASH_TABLE_PAGES) as u32,
+    }
+}
+
+fn page_hash(page_no: u32) -> usize {
+    (page_no as usize).wrapping_mul(383) & (HASH_TABLE_SLOTS - 1)
+}
+
+/// Version is known, so the byte order is the one it reads correctly in
+fn endianness(i: &[u8]) -> IResult<&[u8], Endianness> {
+    peek(alt((
+        map(verify(be_u32, |&v| v == WAL_INDEX_VERSION), |_| {
+            Endianness::Big
+        }),
+        map(verify(le_u32, |&v| v == WAL_INDEX_VERSION), |_| {
+            Endianness::Little
+        }),
+    )))(i)
+}
+
+pub fn wal_index_header(
+    endianness: Endianness,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], WalIndexHeader> {
+    move |i| {
+        let (i, (version, _unused, change_counter)) =
+            (u32(endianness), u32(endianness), u32(endianness)).parse(i)?;
+        let (i, (is_init, big_endian_checksums)) = (u8, u8).parse(i)?;
+        let (i, page_size) = map(u16(endianness), |s| match s {
+            1 => 0x1_00_00,
+            _ => s.into(),
+        })(i)?;
+        let (i, (max_frame, db_size)) = (u32(endianness), u32(endianness)).parse(i)?;
+        let (i, frame_checksum) = (u32(endianness), u32(endianness)).parse(i)?;
+        // salts are copied from the log as is
+        let (i, (salt_1, salt_2)) = (be_u32, be_u32).parse(i)?;
+        let (i, checksum) = (u32(endianness), u32(endianness)).parse(i)?;
+
+        Ok((
+            i,
+            WalIndexHeader {
+                version,
+                change_counter,
+                is_init: is_init != 0,
+                big_endian_checksums: big_endian_checksums != 0,
+                page_size,
+                max_frame,
+                db_size,
+                frame_checksum,
+                salt_1,
+                salt_2,
+                checksum,
+            },
+        ))
+    }
+}
+
+pub fn checkpoint_info(
+    endianness: Endianness,
+) -> impl FnMut(&[u8]) -> IResult<&[u8], CheckpointInfo> {
+    move |i| {
+        let (i, backfill) = u32(endianness)(i)?;
+        let (i, read_marks) = count(u32(endianness), 5)(i)?;
+        let (i, locks) = take(8usize)(i)?;
+        let (i, (backfill_attempted, _unused)) = (u32(endianness), u32(endianness)).parse(i)?;
+
+        Ok((
+            i,
+            CheckpointInfo {
+                backfill,
+                read_marks: read_marks.try_into().unwrap_or_default(),
+                locks: locks.try_into().unwrap_or_default(),
+                backfill_attempted,
+            },
+        ))
+    }
+}
+
+pub struct WalIndex<S: AsRef<[u8]>> {
+    buf: S,
+    endianness: Endianness,
+    /// Both copies of the header, they differ while a writer is updating them
+    pub headers: [WalIndexHeader; 2],
+    pub checkpoint: CheckpointInfo,
+}
+
+impl WalIndex<Mmap> {
+    /// Open a wal-index file by memory mapping it.
+    pub fn open_mmap<P: AsRef<Path>>(shm: P) -> Result<WalIndex<Mmap>, SQLiteError> {
+        let file_read = File::open(shm)?;
+        let mmap = unsafe { MmapOptions::new().map(&file_read) }?;
+        WalIndex::from_source(mmap)
+    }
+}
+
+impl WalIndex<Vec<u8>> {
+    /// Open a wal-index file by loading it into memory.
+    pub fn open_readfile<P: AsRef<Path>>(shm: P) -> Result<WalIndex<Vec<u8>>, SQLiteError> {
+        let buf: Vec<u8> = std::fs::read(shm)?;
+        WalIndex::from_source(buf)
+    }
+}
+
+impl<S: AsRef<[u8]>> WalIndex<S> {
+    /// Open a wal-index from anything that implements AsRef<[u8]>
+    pub fn from_source(buf: S) -> Result<WalIndex<S>, SQLiteError> {
+        let (i, endianness) = endianness(buf.as_ref()).finish()?;
+        let (_, (first, second, checkpoint)) = (
+            wal_index_header(endianness),
+            wal_index_header(endianness),
+            checkpoint_info(endianness),
+        )
+            .parse(i)
+            .finish()?;
+
+        Ok(WalIndex {
+            buf,
+            endianness,
+            headers: [first, second],
+            checkpoint,
+        })
+    }
+
+    /// Written on a big-endian machine
+    pub fn is_big_endian(&self) -> bool {
+        self.endianness == Endianness::Big
+    }
+
+    /// Header is complete: both copies are the same and the checksum matches.
+    pub fn header(&self) -> Option<&WalIndexHeader> {
+        let buf = self.buf.as_ref();
+        let first = &buf[..WAL_INDEX_HEADER_SIZE];
+        let second = &buf[WAL_INDEX_HEADER_SIZE..WAL_INDEX_HEADER_SIZE * 2];
+        let header = &self.headers[0];
+
+        let checksum = wal_checksum(
+            &first[..WAL_INDEX_HEADER_SIZE - 8],
+            self.is_big_endian(),
+            (0, 0),
+        );
+        (first == second && header.is_init && checksum == header.checksum).then_some(header)
+    }
+
+    /// Header matches the log: same salts and page size, the last frame it knows of
+    /// is a commit frame with the expected checksum, and no valid frame follows it.
+    /// A frame continuing the checksum chain means the index is stale, written before
+    /// later transactions.
+    pub fn is_consistent_with<W: AsRef<[u8]>>(
+        &self,
+        wal: &WalReader<W>,
+    ) -> Result<bool, SQLiteError> {
+        let header = match self.header() {
+            Some(header) => header,
+            None => return Ok(false),
+        };
+        if header.salt_1 != wal.header.salt_1
+            || header.salt_2 != wal.header.salt_2
+            || header.page_size as usize != wal.page_size()
+        {
+            return Ok(false);
+        }
+
+        let last_known = match wal.frame(header.max_frame)? {
+            Some(frame) => {
+                frame.header.is_commit()
+                    && wal.is_current(&frame.header)
+                    && (frame.header.checksum_1, frame.header.checksum_2) == header.frame_checksum
+            }
+            None => header.max_frame == 0,
+        };
+        if !last_known {
+            return Ok(false);
+        }
+
+        let seed = match header.max_frame {
+            0 => (wal.header.checksum_1, wal.header.checksum_2),
+            _ => header.frame_checksum,
+        };
+        Ok(match wal.frame(header.max_frame + 1)? {
+            Some(next) => {
+                !wal.is_current(&next.header)
+                    || wal.frame_checksum(&next, seed)
+                        != (next.header.checksum_1, next.header.checksum_2)
+            }
+            None => true,
+        })
+    }
+
+    fn word(&self, offset: usize) -> Option<u32> {
+        let bytes = self.buf.as_ref().get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.endianness {
+            Endianness::Big => u32::from_be_bytes(bytes),
+            _ => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn half_word(&self, offset: usize) -> Option<u16> {
+        let bytes = self.buf.as_ref().get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.endianness {
+            Endianness::Big => u16::from_be_bytes(bytes),
+            _ => u16::from_le_bytes(bytes),
+        })
+    }
+
+    /// Page stored in the frame, `None` if the frame is not indexed.
+    pub fn page_no(&self, frame_no: u32) -> Option<u32> {
+        if frame_no == 0 {
+            return None;
+        }
+
+        let block = block_of(frame_no);
+        let position = (frame_no - block_base(block)) as usize - 1;
+        let page_nos_start = match block {
+            0 => WAL_INDEX_HEADERS_SIZE,
+            _ => block * WAL_INDEX_BLOCK_SIZE,
+        };
+
+        self.word(page_nos_start + position * 4).filter(|&p| p != 0)
+    }
+
+    /// Latest frame up to `max_frame` holding the page, found through the hash tables.
+    pub fn frame_no(&self, page_no: u32, max_frame: u32) -> Option<u32> {
+        if max_frame == 0 {
+            return None;
+        }
+
+        (0..=block_of(max_frame)).rev().find_map(|block| {
+            let slots_start = block * WAL_INDEX_BLOCK_SIZE + HASH_TABLE_PAGES * 4;
+            let mut found = None;
+
+            // open addressing, later frames of the same page are further along the chain
+            let mut slot = page_hash(page_no);
+            for _ in 0..HASH_TABLE_SLOTS {
+                let position = self.half_word(slots_start + slot * 2)?;
+                if position == 0 {
+                    break;
+                }
+
+                let frame_no = block_base(block) + position as u32;
+                if frame_no <= max_frame && self.page_no(frame_no) == Some(page_no) {
+                    found = Some(frame_no);
+                }
+                slot = (slot + 1) & (HASH_TABLE_SLOTS - 1);
+            }
+
+            found
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_positions_in_blocks() {
+        assert_eq!(block_of(1), 0);
+        assert_eq!(block_of(4062), 0);
+        assert_eq!(block_of(4063), 1);
+        assert_eq!(block_of(4062 + 4096), 1);
+        assert_eq!(block_of(4062 + 4096 + 1), 2);
+
+        assert_eq!(block_base(0), 0);
+        assert_eq!(block_base(1), 4062);
+        assert_eq!(block_base(2), 4062 + 4096);
+    }
+}
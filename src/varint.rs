@@ -1,3 +1,4 @@
+use nom::combinator::map;
 use nom::error::{ErrorKind, ParseError};
 use nom::Err;
 use nom::IResult;
@@ -6,12 +7,17 @@ use nom::IResult;
 ///
 /// Most-significant bit is used as a flag if next byte should taken.
 /// It is discarded and the rest are concatenated into resulting integer.
+/// The ninth byte, if reached, contributes all of its 8 bits.
 pub fn be_u64_varint(i: &[u8]) -> IResult<&[u8], u64> {
     let mut res = 0;
     // to guard from overflow
-    let max_slice = &i[0..(i.len().min(5))];
+    let max_slice = &i[0..(i.len().min(9))];
     for (id, &b) in max_slice.iter().enumerate() {
         let b = b as u64;
+        if id == 8 {
+            return Ok((&i[id + 1..], (res << 8) | b));
+        }
+
         res = (res << 7) | (b & 0b0111_1111);
 
         if b >> 7 == 0 {
@@ -25,9 +31,24 @@ pub fn be_u64_varint(i: &[u8]) -> IResult<&[u8], u64> {
     )))
 }
 
+/// Same varint interpreted as two-complimentary signed integer, as rowids are.
+pub fn be_i64_varint(i: &[u8]) -> IResult<&[u8], i64> {
+    map(be_u64_varint, |x| x as i64)(i)
+}
+
+/// Number of bytes the value takes when encoded as a varint.
+pub fn varint_len(value: u64) -> usize {
+    match 64 - value.leading_zeros() as usize {
+        // the ninth byte carries 8 bits
+        bits if bits > 56 => 9,
+        bits => bits.max(1).div_ceil(7),
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)] // grouped by 7 bits of payload
 mod tests {
-    use crate::varint::be_u64_varint;
+    use crate::varint::{be_i64_varint, be_u64_varint, varint_len};
 
     #[test]
     fn parse_1_byte() {
@@ -94,4 +115,49 @@ mod tests {
 
         assert_eq!(i.len(), 1);
     }
+
+    #[test]
+    fn parse_9_byte() {
+        let varint = [0xff; 9];
+        let (i, res) = be_u64_varint(&varint).unwrap();
+
+        assert!(i.is_empty());
+        assert_eq!(res, u64::MAX);
+    }
+
+    #[test]
+    fn parse_9_byte_uses_all_bits_of_last() {
+        let varint = [0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0x42];
+        let (i, res) = be_u64_varint(&varint).unwrap();
+
+        assert_eq!(i, &[0x42]);
+        assert_eq!(res, (1 << 57) | 1);
+    }
+
+    #[test]
+    fn parse_signed() {
+        let (_, res) = be_i64_varint(&[0xff; 9]).unwrap();
+        assert_eq!(res, -1);
+
+        let varint = [0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        let (_, res) = be_i64_varint(&varint).unwrap();
+        assert_eq!(res, i64::MIN);
+    }
+
+    #[test]
+    fn fails_on_short_input() {
+        assert!(be_u64_varint(&[]).is_err());
+        assert!(be_u64_varint(&[0x80, 0x80]).is_err());
+        assert!(be_u64_varint(&[0xff; 8]).is_err());
+    }
+
+    #[test]
+    fn encoded_length() {
+        assert_eq!(varint_len(0), 1);
+        assert_eq!(varint_len(0x7f), 1);
+        assert_eq!(varint_len(0x80), 2);
+        assert_eq!(varint_len(0x00ff_ffff_ffff_ffff), 8);
+        assert_eq!(varint_len(0x0100_0000_0000_0000), 9);
+        assert_eq!(varint_len(u64::MAX), 9);
+    }
 }
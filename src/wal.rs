@@ -0,0 +1,474 @@
+//! Write-ahead log, the `-wal` file next to a database in WAL mode.
+//!
+//! The log starts with a 32-byte header followed by frames, each being a 24-byte frame header
+//! and a page image. Frame numbers are 1-based, as in SQLite.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapOptions};
+use nom::bytes::complete::take;
+use nom::combinator::verify;
+use nom::number::complete::be_u32;
+use nom::sequence::Tuple;
+use nom::{Finish, IResult};
+
+use crate::error::SQLiteError;
+use crate::model::{Page, PageLayout};
+use crate::parser::{page_with_layout, root_page_with_layout};
+use crate::shm::WalIndex;
+
+pub const WAL_HEADER_SIZE: usize = 32;
+pub const WAL_FRAME_HEADER_SIZE: usize = 24;
+
+/// Checksums are computed on little-endian words
+pub const WAL_MAGIC_LE: u32 = 0x377f0682;
+/// Checksums are computed on big-endian words
+pub const WAL_MAGIC_BE: u32 = 0x377f0683;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WalHeader {
+    pub magic: u32,
+    pub file_format: u32,
+    pub page_size: u32,
+    pub checkpoint_seq: u32,
+    /// Incremented with every checkpoint
+    pub salt_1: u32,
+    /// Random on every checkpoint
+    pub salt_2: u32,
+    pub checksum_1: u32,
+    pub checksum_2: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WalFrameHeader {
+    pub page_no: u32,
+    /// Database size in pages after the commit, 0 for frames which are not commit frames
+    pub db_size_after_commit: u32,
+    pub salt_1: u32,
+    pub salt_2: u32,
+    /// Cumulative checksum over the log header and all the frames up to this one
+    pub checksum_1: u32,
+    pub checksum_2: u32,
+}
+
+impl WalHeader {
+    /// Checksum words are big-endian, otherwise little-endian
+    pub fn big_endian_checksums(&self) -> bool {
+        self.magic == WAL_MAGIC_BE
+    }
+}
+
+impl WalFrameHeader {
+    /// Last frame of a transaction
+    pub fn is_commit(&self) -> bool {
+        self.db_size_after_commit != 0
+    }
+}
+
+/// Last frame of a transaction committed to the log
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WalCommit {
+    /// 1-based position of the commit frame in the log
+    pub frame_no: u32,
+    /// Database size in pages after the commit
+    pub db_size: u32,
+    pub salt_1: u32,
+    pub salt_2: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WalFrame<'a> {
+    /// 1-based position of the frame in the log
+    pub frame_no: u32,
+    pub header: WalFrameHeader,
+    /// Page image, exactly page size long
+    pub page_bytes: &'a [u8],
+}
+
+impl<'a> WalFrame<'a> {
+    /// Transaction ending with this frame, `None` for frames in the middle of one.
+    pub fn commit(&self) -> Option<WalCommit> {
+        self.header.is_commit().then_some(WalCommit {
+            frame_no: self.frame_no,
+            db_size: self.header.db_size_after_commit,
+            salt_1: self.header.salt_1,
+            salt_2: self.header.salt_2,
+        })
+    }
+
+    /// Decodes the page image, the first page includes the database header.
+    /// `layout` is taken from the database header, as frames don't tell the reserved space.
+    pub fn page(&self, layout: PageLayout) -> Result<Page<'a>, SQLiteError> {
+        let (_, page) = if self.header.page_no == 1 {
+            root_page_with_layout(layout)(self.page_bytes)
+        } else {
+            page_with_layout(layout)(self.page_bytes)
+        }
+        .finish()?;
+
+        Ok(page)
+    }
+}
+
+/// Cumulative checksum SQLite uses for the log, computed over pairs of 32-bit words
+/// and continuing from the checksum of everything before.
+pub fn wal_checksum(data: &[u8], big_endian: bool, seed: (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    data.chunks_exact(8).fold(seed, |(s1, s2), chunk| {
+        let s1 = s1.wrapping_add(word(&chunk[..4])).wrapping_add(s2);
+        let s2 = s2.wrapping_add(word(&chunk[4..])).wrapping_add(s1);
+        (s1, s2)
+    })
+}
+
+pub fn wal_header(i: &[u8]) -> IResult<&[u8], WalHeader> {
+    let (i, magic) = verify(be_u32, |&m| m == WAL_MAGIC_LE || m == WAL_MAGIC_BE)(i)?;
+    let (i, file_format) = be_u32(i)?;
+    let (i, page_size) = verify(be_u32, |&s| {
+        s.is_power_of_two() && (512..=0x1_00_00).contains(&s)
+    })(i)?;
+    let (i, (checkpoint_seq, salt_1, salt_2)) = (be_u32, be_u32, be_u32).parse(i)?;
+    let (i, (checksum_1, checksum_2)) = (be_u32, be_u32).parse(i)?;
+
+    Ok((
+        i,
+        WalHeader {
+            magic,
+            file_format,
+            page_size,
+            checkpoint_seq,
+            salt_1,
+            salt_2,
+            checksum_1,
+            checksum_2,
+        },
+    ))
+}
+
+pub fn wal_frame_header(i: &[u8]) -> IResult<&[u8], WalFrameHeader> {
+    let (i, (page_no, db_size_after_commit)) = (be_u32, be_u32).parse(i)?;
+    let (i, (salt_1, salt_2)) = (be_u32, be_u32).parse(i)?;
+    let (i, (checksum_1, checksum_2)) = (be_u32, be_u32).parse(i)?;
+
+    Ok((
+        i,
+        WalFrameHeader {
+            page_no,
+            db_size_after_commit,
+            salt_1,
+            salt_2,
+            checksum_1,
+            checksum_2,
+        },
+    ))
+}
+
+/// Frame header followed by the page image
+pub fn wal_frame(page_size: usize) -> impl FnMut(&[u8]) -> IResult<&[u8], (WalFrameHeader, &[u8])> {
+    move |i| (wal_frame_header, take(page_size)).parse(i)
+}
+
+pub struct WalReader<S: AsRef<[u8]>> {
+    buf: S,
+    pub header: WalHeader,
+}
+
+impl WalReader<Mmap> {
+    /// Open a WAL file by memory mapping it.
+    pub fn open_mmap<P: AsRef<Path>>(wal: P) -> Result<WalReader<Mmap>, SQLiteError> {
+        let file_read = File::open(wal)?;
+        let mmap = unsafe { MmapOptions::new().map(&file_read) }?;
+        WalReader::from_source(mmap)
+    }
+}
+
+impl WalReader<Vec<u8>> {
+    /// Open a WAL file by loading it into memory.
+    pub fn open_readfile<P: AsRef<Path>>(wal: P) -> Result<WalReader<Vec<u8>>, SQLiteError> {
+        let buf: Vec<u8> = std::fs::read(wal)?;
+        WalReader::from_source(buf)
+    }
+}
+
+impl<S: AsRef<[u8]>> WalReader<S> {
+    /// Open a WAL from anything that implements AsRef<[u8]>
+    pub fn from_source(buf: S) -> Result<WalReader<S>, SQLiteError> {
+        let (_, header) = wal_header(buf.as_ref()).finish()?;
+
+        Ok(WalReader { buf, header })
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.header.page_size as usize
+    }
+
+    /// Number of complete frames in the file, a partially written last frame is not counted.
+    pub fn frame_count(&self) -> u32 {
+        let frames_size = self.buf.as_ref().len().saturating_sub(WAL_HEADER_SIZE);
+        (frames_size / (WAL_FRAME_HEADER_SIZE + self.page_size())) as u32
+    }
+
+    /// Frame by its 1-based number, `None` past the end of the log.
+    pub fn frame(&self, frame_no: u32) -> Result<Option<WalFrame<'_>>, SQLiteError> {
+        if frame_no == 0 || frame_no > self.frame_count() {
+            return Ok(None);
+        }
+
+        let frame_size = WAL_FRAME_HEADER_SIZE + self.page_size();
+        let start = WAL_HEADER_SIZE + (frame_no as usize - 1) * frame_size;
+        let (_, (header, page_bytes)) =
+            wal_frame(self.page_size())(&self.buf.as_ref()[start..]).finish()?;
+
+        Ok(Some(WalFrame {
+            frame_no,
+            header,
+            page_bytes,
+        }))
+    }
+
+    /// All the complete frames in the log order, including uncommitted ones
+    /// and leftovers from before the last checkpoint.
+    pub fn frames(&self) -> impl Iterator<Item = Result<WalFrame<'_>, SQLiteError>> + '_ {
+        (1..=self.frame_count()).filter_map(|frame_no| self.frame(frame_no).transpose())
+    }
+
+    /// Frames written since the last checkpoint carry the salts of the log header.
+    pub fn is_current(&self, frame: &WalFrameHeader) -> bool {
+        frame.salt_1 == self.header.salt_1 && frame.salt_2 == self.header.salt_2
+    }
+
+    /// Checksum stored in the header matches the one of its first 24 bytes.
+    pub fn is_header_valid(&self) -> bool {
+        let checksum = wal_checksum(
+            &self.buf.as_ref()[..WAL_HEADER_SIZE - 8],
+            self.header.big_endian_checksums(),
+            (0, 0),
+        );
+        checksum == (self.header.checksum_1, self.header.checksum_2)
+    }
+
+    /// Running checksum after the frame, `seed` being the one of the previous frame
+    /// or of the header for the first frame.
+    pub fn frame_checksum(&self, frame: &WalFrame, seed: (u32, u32)) -> (u32, u32) {
+        let big_endian = self.header.big_endian_checksums();
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(&frame.header.page_no.to_be_bytes());
+        header[4..].copy_from_slice(&frame.header.db_size_after_commit.to_be_bytes());
+
+        let checksum = wal_checksum(&header, big_endian, seed);
+        wal_checksum(frame.page_bytes, big_endian, checksum)
+    }
+
+    /// Transactions committed since the last checkpoint, in the log order.
+    /// Frames after the first one failing verification are not considered.
+    pub fn commits(&self) -> Vec<WalCommit> {
+        self.verified_frames()
+            .map_while(Result::ok)
+            .filter_map(|frame| frame.commit())
+            .collect()
+    }
+
+    pub(crate) fn as_slice(&self) -> WalReader<&[u8]> {
+        WalReader {
+            buf: self.buf.as_ref(),
+            header: self.header,
+        }
+    }
+
+    /// Frames written since the last checkpoint, in the log order, with their checksums verified.
+    /// The walk ends with an error at the first frame which fails verification,
+    /// which is where SQLite recovery stops too.
+    pub fn verified_frames(&self) -> VerifiedFrames<'_, S> {
+        VerifiedFrames {
+            wal: self,
+            next_frame_no: 1,
+            checksum: Some((self.header.checksum_1, self.header.checksum_2)),
+        }
+    }
+}
+
+pub struct VerifiedFrames<'a, S: AsRef<[u8]>> {
+    wal: &'a WalReader<S>,
+    next_frame_no: u32,
+    /// Running checksum, `None` once the walk is over
+    checksum: Option<(u32, u32)>,
+}
+
+impl<'a, S: AsRef<[u8]>> Iterator for VerifiedFrames<'a, S> {
+    type Item = Result<WalFrame<'a>, SQLiteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let checksum = self.checksum.take()?;
+        if self.next_frame_no == 1 && !self.wal.is_header_valid() {
+            return Some(Err(SQLiteError::WalHeaderChecksumError));
+        }
+
+        let frame = match self.wal.frame(self.next_frame_no) {
+            Ok(Some(frame)) if self.wal.is_current(&frame.header) => frame,
+            Ok(_) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let checksum = self.wal.frame_checksum(&frame, checksum);
+        if frame.header.page_no == 0
+            || checksum != (frame.header.checksum_1, frame.header.checksum_2)
+        {
+            return Some(Err(SQLiteError::InvalidWalFrameError(frame.frame_no)));
+        }
+
+        self.checksum = Some(checksum);
+        self.next_frame_no += 1;
+        Some(Ok(frame))
+    }
+}
+
+/// How the frame holding the latest version of a page is found
+enum FrameLookup<S: AsRef<[u8]>> {
+    /// Page number to frame number, built by replaying the log
+    Scanned(HashMap<u32, u32>),
+    /// Hash tables of the wal-index, up to the last frame it knows of
+    Indexed { index: WalIndex<S>, max_frame: u32 },
+}
+
+/// Version of every page in the log as of a commit
+pub(crate) struct WalSnapshot<S: AsRef<[u8]>> {
+    pub(crate) wal: WalReader<S>,
+    /// All the valid commits in the log, including the ones after the snapshot
+    pub(crate) commits: Vec<WalCommit>,
+    frames: FrameLookup<S>,
+    /// Database size in pages after the commit, `None` if nothing was committed
+    pub(crate) db_size: Option<u32>,
+    /// Verification failure which ended the replay early
+    pub(crate) error: Option<SQLiteError>,
+}
+
+impl<S: AsRef<[u8]>> WalSnapshot<S> {
+    /// Replays the log up to the last commit frame, stopping at the first frame
+    /// which is left from before the last checkpoint or fails verification.
+    pub(crate) fn new(wal: WalReader<S>) -> Result<Self, SQLiteError> {
+        Self::at_commit(wal, None)
+    }
+
+    /// Replays the log up to the commit with the given index among the valid commits,
+    /// or the last one if `None`.
+    pub(crate) fn at_commit(wal: WalReader<S>, commit: Option<usize>) -> Result<Self, SQLiteError> {
+        let mut frames = HashMap::new();
+        let mut uncommitted = Vec::new();
+        let mut db_size = None;
+        let mut error = None;
+
+        let mut commits = Vec::new();
+        for frame in wal.verified_frames() {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            };
+
+            let applied = commit.map_or(true, |commit| commits.len() <= commit);
+            if applied {
+                uncommitted.push((frame.header.page_no, frame.frame_no));
+            }
+            if let Some(wal_commit) = frame.commit() {
+                if applied {
+                    frames.extend(uncommitted.drain(..));
+                    db_size = Some(wal_commit.db_size);
+                }
+                commits.push(wal_commit);
+            }
+        }
+
+        if let Some(commit) = commit.filter(|&c| c >= commits.len()) {
+            return Err(SQLiteError::WalCommitOutOfBoundsError(commit));
+        }
+
+        Ok(WalSnapshot {
+            wal,
+            commits,
+            frames: FrameLookup::Scanned(frames),
+            db_size,
+            error,
+        })
+    }
+
+    /// Trusts the wal-index if it's consistent with the log, the same way SQLite readers do,
+    /// so the log doesn't have to be replayed. Otherwise replays the log.
+    pub(crate) fn with_index(wal: WalReader<S>, index: WalIndex<S>) -> Result<Self, SQLiteError> {
+        let header = match index.header() {
+            Some(&header) if index.is_consistent_with(&wal)? => header,
+            _ => return Self::new(wal),
+        };
+
+        let commits = wal
+            .frames()
+            .take(header.max_frame as usize)
+            .filter_map(|frame| frame.map(|f| f.commit()).transpose())
+            .collect::<Result<_, _>>()?;
+
+        Ok(WalSnapshot {
+            wal,
+            commits,
+            frames: FrameLookup::Indexed {
+                index,
+                max_frame: header.max_frame,
+            },
+            db_size: (header.max_frame != 0).then_some(header.db_size),
+            error: None,
+        })
+    }
+
+    /// The wal-index is used for lookups
+    pub(crate) fn is_indexed(&self) -> bool {
+        matches!(self.frames, FrameLookup::Indexed { .. })
+    }
+
+    /// Page image from the log, `None` if the page wasn't changed since the last checkpoint.
+    pub(crate) fn page_bytes(&self, page_no: u32) -> Result<Option<&[u8]>, SQLiteError> {
+        let frame_no = match &self.frames {
+            FrameLookup::Scanned(frames) => frames.get(&page_no).copied(),
+            FrameLookup::Indexed { index, max_frame } => index.frame_no(page_no, *max_frame),
+        };
+
+        match frame_no {
+            Some(frame_no) => Ok(self.wal.frame(frame_no)?.map(|f| f.page_bytes)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_in_both_byte_orders() {
+        let data = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4];
+
+        // s1 = 1 + 0, s2 = 2 + 1, then s1 = 1 + 3 + 3, s2 = 3 + 4 + 7
+        assert_eq!(wal_checksum(&data, true, (0, 0)), (7, 14));
+        assert_eq!(
+            wal_checksum(&data, false, (0, 0)),
+            (0x0700_0000, 0x0e00_0000)
+        );
+        assert_eq!(
+            wal_checksum(&data[8..], true, wal_checksum(&data[..8], true, (0, 0))),
+            (7, 14)
+        );
+        assert_eq!(
+            wal_checksum(&[0xff; 8], true, (u32::MAX, 0)),
+            (u32::MAX - 1, u32::MAX - 2)
+        );
+    }
+}